@@ -0,0 +1,221 @@
+//! Anti-aliasing of whole frames, by filtering a supersampled frame down or with FXAA
+
+use crate::buffer::FrameBuffer;
+use crate::data::{Add, ScalarDiv, ScalarMul, Vec3};
+
+/// Filter that turns the pixels of a supersampled frame into the pixels of the final frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Downsample
+{
+    /// Averages the block of samples of each pixel
+    Box,
+    /// Windowed sinc over two pixels of the final frame around each one, sharper than `Box`
+    Lanczos,
+}
+
+/// Lobes of the Lanczos kernel
+const LANCZOS_A: f32 = 2.0;
+
+/// Filters `src` down into `dst`, whose width and height divide those of `src` by the same factor
+pub fn downsample(src: &FrameBuffer, dst: &mut FrameBuffer, filter: Downsample)
+{
+    let factor = src.width() / dst.width();
+    assert!(factor > 0 && src.width() == dst.width() * factor && src.height() == dst.height() * factor,
+            "a {}x{} frame is not a multiple of a {}x{} frame", src.width(), src.height(), dst.width(), dst.height());
+    match filter {
+        Downsample::Box => {
+            let samples = (factor * factor) as f32;
+            for y in 0..dst.height()
+            {
+                for x in 0..dst.width()
+                {
+                    let mut sum = Vec3::new(0.0);
+                    for j in y * factor..(y + 1) * factor
+                    {
+                        for i in x * factor..(x + 1) * factor
+                        {
+                            sum.add_(&src.get_f32(i, j));
+                        }
+                    }
+                    dst.set(x, y, sum.scalar_div(samples));
+                }
+            }
+        }
+        Downsample::Lanczos => {
+            // the kernel is separable, so filter the rows first and then the columns
+            let taps = lanczos_taps(src.width(), dst.width(), factor);
+            let mut rows = vec![Vec3::new(0.0); dst.width() * src.height()];
+            for j in 0..src.height()
+            {
+                for x in 0..dst.width()
+                {
+                    rows[j * dst.width() + x] = taps[x].iter()
+                        .fold(Vec3::new(0.0), |sum, (i, w)| sum._add(&src.get_f32(*i, j).scalar_mul(*w)));
+                }
+            }
+            let taps = lanczos_taps(src.height(), dst.height(), factor);
+            for y in 0..dst.height()
+            {
+                for x in 0..dst.width()
+                {
+                    let color = taps[y].iter()
+                        .fold(Vec3::new(0.0), |sum, (j, w)| sum._add(&rows[j * dst.width() + x].scalar_mul(*w)));
+                    // the negative lobes may ring below black next to bright edges
+                    dst.set(x, y, Vec3::new_rgb(color.r().max(0.0), color.g().max(0.0), color.b().max(0.0)));
+                }
+            }
+        }
+    }
+}
+
+/// Source pixels and their normalized weights for each pixel of a row or column shrunk by `factor`
+fn lanczos_taps(src_size: usize, dst_size: usize, factor: usize) -> Vec<Vec<(usize, f32)>>
+{
+    let kernel = |x: f32| {
+        if x == 0.0
+        {
+            return 1.0;
+        }
+        if x.abs() >= LANCZOS_A
+        {
+            return 0.0;
+        }
+        let pi_x = std::f32::consts::PI * x;
+        return LANCZOS_A * pi_x.sin() * (pi_x / LANCZOS_A).sin() / (pi_x * pi_x);
+    };
+    let radius = (LANCZOS_A * factor as f32).ceil() as isize;
+    return (0..dst_size).map(|d| {
+        let center = (d as f32 + 0.5) * factor as f32;
+        let first = (center as isize - radius).max(0);
+        let last = (center as isize + radius).min(src_size as isize);
+        let mut taps: Vec<(usize, f32)> = (first..last)
+            .map(|s| (s as usize, kernel((s as f32 + 0.5 - center) / factor as f32)))
+            .filter(|(_, w)| *w != 0.0)
+            .collect();
+        // taps cut off by the border are left out, so the weights are normalized again
+        let sum: f32 = taps.iter().map(|(_, w)| w).sum();
+        taps.iter_mut().for_each(|(_, w)| *w /= sum);
+        return taps;
+    }).collect();
+}
+
+/// Contrast between the lumas around a pixel, relative to the brightest one, below which it is left alone
+const FXAA_EDGE_THRESHOLD: f32 = 1.0 / 8.0;
+/// Contrast below which a pixel is left alone in dark areas
+const FXAA_EDGE_THRESHOLD_MIN: f32 = 1.0 / 16.0;
+/// Smallest damping of the edge direction, which keeps it bounded where the corners are dark
+const FXAA_REDUCE_MIN: f32 = 1.0 / 128.0;
+/// Damping of the edge direction relative to the average luma of the corners
+const FXAA_REDUCE_MUL: f32 = 1.0 / 8.0;
+/// Longest blur along an edge in pixels
+const FXAA_SPAN_MAX: f32 = 8.0;
+
+#[inline]
+fn luma(color: &Vec3) -> f32
+{
+    0.299 * color.r() + 0.587 * color.g() + 0.114 * color.b()
+}
+
+/// Bilinear sample of the float colors at `x, y` in pixels, clamped to the frame
+fn sample(frame: &FrameBuffer, x: f32, y: f32) -> Vec3
+{
+    let (x, y) = ((x - 0.5).max(0.0), (y - 0.5).max(0.0));
+    let (x0, y0) = ((x as usize).min(frame.width() - 1), (y as usize).min(frame.height() - 1));
+    let (x1, y1) = ((x0 + 1).min(frame.width() - 1), (y0 + 1).min(frame.height() - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let bottom = frame.get_f32(x0, y0).scalar_mul(1.0 - fx)._add(&frame.get_f32(x1, y0).scalar_mul(fx));
+    let top = frame.get_f32(x0, y1).scalar_mul(1.0 - fx)._add(&frame.get_f32(x1, y1).scalar_mul(fx));
+    return bottom.scalar_mul(1.0 - fy)._add(&top.scalar_mul(fy));
+}
+
+/// Fast approximate anti-aliasing of a finished frame
+///
+/// Pixels of low contrast with their four diagonal neighbours are kept. Elsewhere, the luma of
+/// the neighbours gives the direction of an edge through the pixel, which is blurred along it.
+/// Blurs that would take in colors brighter or darker than the neighbourhood fall back to a
+/// shorter one.
+pub fn fxaa(frame: &FrameBuffer) -> FrameBuffer
+{
+    let mut filtered = FrameBuffer::new(frame.width(), frame.height());
+    let (w, h) = (frame.width() as isize, frame.height() as isize);
+    let luma_at = |x: isize, y: isize| luma(&frame.get_f32(x.max(0).min(w - 1) as usize, y.max(0).min(h - 1) as usize));
+    for y in 0..h
+    {
+        for x in 0..w
+        {
+            let color = frame.get_f32(x as usize, y as usize);
+            let luma_m = luma(&color);
+            let (luma_nw, luma_ne) = (luma_at(x - 1, y + 1), luma_at(x + 1, y + 1));
+            let (luma_sw, luma_se) = (luma_at(x - 1, y - 1), luma_at(x + 1, y - 1));
+            let luma_min = luma_m.min(luma_nw).min(luma_ne).min(luma_sw).min(luma_se);
+            let luma_max = luma_m.max(luma_nw).max(luma_ne).max(luma_sw).max(luma_se);
+            if luma_max - luma_min < f32::max(FXAA_EDGE_THRESHOLD_MIN, luma_max * FXAA_EDGE_THRESHOLD)
+            {
+                filtered.set(x as usize, y as usize, color);
+                continue;
+            }
+            // the edge runs across the gradient of the luma
+            let dir_x = -((luma_nw + luma_ne) - (luma_sw + luma_se));
+            let dir_y = (luma_nw + luma_sw) - (luma_ne + luma_se);
+            let dir_reduce = f32::max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+            let scale = 1.0 / (dir_x.abs().min(dir_y.abs()) + dir_reduce);
+            let dir_x = (dir_x * scale).max(-FXAA_SPAN_MAX).min(FXAA_SPAN_MAX);
+            let dir_y = (dir_y * scale).max(-FXAA_SPAN_MAX).min(FXAA_SPAN_MAX);
+            let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+            let along = |t: f32| sample(frame, cx + dir_x * t, cy + dir_y * t);
+            let near = along(1.0 / 3.0 - 0.5)._add(&along(2.0 / 3.0 - 0.5)).scalar_mul(0.5);
+            let far = near.scalar_mul(0.5)._add(&along(-0.5)._add(&along(0.5)).scalar_mul(0.25));
+            let luma_far = luma(&far);
+            let blurred = if luma_far < luma_min || luma_far > luma_max { near } else { far };
+            filtered.set(x as usize, y as usize, blurred);
+        }
+    }
+    return filtered;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_downsample() {
+        // a checkerboard of single pixels averages out to gray
+        let mut src = FrameBuffer::new(8, 6);
+        for y in 0..6
+        {
+            for x in 0..8
+            {
+                src.set(x, y, Vec3::new(((x + y) % 2) as f32));
+            }
+        }
+        let mut dst = FrameBuffer::new(4, 3);
+        downsample(&src, &mut dst, Downsample::Box);
+        assert!(dst.as_f32().iter().all(|c| c.r() == 0.5 && c.b() == 0.5));
+
+        // Lanczos keeps flat areas flat, also at the borders
+        src.clear(Vec3::new_rgb(0.25, 0.5, 1.0));
+        downsample(&src, &mut dst, Downsample::Lanczos);
+        assert!(dst.as_f32().iter().all(|c| (c.r() - 0.25).abs() < 1e-5 && (c.b() - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_fxaa_smooths_edges_only() {
+        // white below a staircase going up two pixels per column, black above it
+        let (width, height) = (16, 32);
+        let mut frame = FrameBuffer::new(width, height);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                frame.set(x, y, Vec3::new(if y < 2 * x { 1.0 } else { 0.0 }));
+            }
+        }
+        let filtered = fxaa(&frame);
+        // some pixels along the staircase end up between black and white
+        let blended = filtered.as_f32().iter().filter(|c| c.r() > 0.05 && c.r() < 0.95).count();
+        assert!(blended > width);
+        // far from it nothing changes
+        assert_eq!(filtered.get_f32(12, 2).r(), 1.0);
+        assert_eq!(filtered.get_f32(1, 28).r(), 0.0);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::data::{Add, Length, Minus, ScalarMul, Vec3};
+use crate::shading::Vertex;
+
+/// Smallest distance to the near plane, relative to the bounding radius
+const MIN_NEAR_IN_RADIUS: f32 = 1e-3;
+/// Smallest distance to the near plane, for spheres of zero radius
+const MIN_NEAR: f32 = 1e-4;
+/// Extra room left around the bounding sphere when fitting the camera and the clipping planes
+const FIT_MARGIN: f32 = 1.1;
+
+/// Axis-aligned bounding box
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingBox
+{
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl BoundingBox
+{
+    /// Returns `None` for an empty vertex list
+    pub fn from_vertices(vertices: &Vec<Vertex>) -> Option<Self>
+    {
+        let mut iter = vertices.iter().map(|v| Vec3::new_xyz(v.x() / v.w(), v.y() / v.w(), v.z() / v.w()));
+        let first = iter.next()?;
+        let mut bbox = BoundingBox {
+            min: first,
+            max: first,
+        };
+        for p in iter
+        {
+            bbox.min = Vec3::new_xyz(f32::min(bbox.min.x(), p.x()), f32::min(bbox.min.y(), p.y()), f32::min(bbox.min.z(), p.z()));
+            bbox.max = Vec3::new_xyz(f32::max(bbox.max.x(), p.x()), f32::max(bbox.max.y(), p.y()), f32::max(bbox.max.z(), p.z()));
+        }
+        return Some(bbox);
+    }
+
+    pub fn center(&self) -> Vec3
+    {
+        self.min._add(&self.max).scalar_mul(0.5)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingSphere
+{
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere
+{
+    /// A sphere around the center of the bounding box, just large enough to hold every vertex
+    pub fn from_vertices(vertices: &Vec<Vertex>) -> Option<Self>
+    {
+        let center = BoundingBox::from_vertices(vertices)?.center();
+        let radius = vertices.iter()
+            .map(|v| Vec3::new_xyz(v.x() / v.w(), v.y() / v.w(), v.z() / v.w())._minus(&center).get_length())
+            .fold(0.0, f32::max);
+        return Some(BoundingSphere {
+            center,
+            radius,
+        });
+    }
+
+    /// Distance from the center at which a camera sees the whole sphere
+    pub fn fit_distance(&self, fov_y_rad: f32, aspect: f32) -> f32
+    {
+        let half_fov_y = fov_y_rad * 0.5;
+        let half_fov_x = (half_fov_y.tan() * aspect).atan();
+        let half_fov = f32::min(half_fov_x, half_fov_y);
+        return FIT_MARGIN * self.radius / half_fov.sin();
+    }
+
+    /// Near and far planes that keep the sphere unclipped while the camera orbits `target` at the
+    /// distance of `eye`
+    pub fn clip_planes(&self, eye: &Vec3, target: &Vec3) -> (f32, f32)
+    {
+        let orbit_radius = eye._minus(target).get_length();
+        let target_to_center = target._minus(&self.center).get_length();
+        let closest = orbit_radius - target_to_center - self.radius;
+        let farthest = orbit_radius + target_to_center + self.radius;
+        let min_near = f32::max(MIN_NEAR_IN_RADIUS * self.radius, MIN_NEAR);
+        let near = f32::max(closest / FIT_MARGIN, min_near);
+        let far = f32::max(farthest * FIT_MARGIN, near * 2.0);
+        return (near, far);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::Vec4;
+
+    use super::*;
+
+    fn vertices() -> Vec<Vertex>
+    {
+        vec![
+            Vertex { position: Vec4::new_xyzw(0.0, 0.0, 0.0, 1.0), idx: 0 },
+            Vertex { position: Vec4::new_xyzw(2.0, 4.0, -2.0, 1.0), idx: 1 },
+            Vertex { position: Vec4::new_xyzw(2.0, 2.0, 0.0, 2.0), idx: 2 },
+        ]
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let bbox = BoundingBox::from_vertices(&vertices()).unwrap();
+        assert_eq!((bbox.min.x(), bbox.min.y(), bbox.min.z()), (0.0, 0.0, -2.0));
+        assert_eq!((bbox.max.x(), bbox.max.y(), bbox.max.z()), (2.0, 4.0, 0.0));
+        let center = bbox.center();
+        assert_eq!((center.x(), center.y(), center.z()), (1.0, 2.0, -1.0));
+        assert!(BoundingBox::from_vertices(&Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_bounding_sphere_holds_all_vertices() {
+        let sphere = BoundingSphere::from_vertices(&vertices()).unwrap();
+        assert_eq!(sphere.radius, 6.0_f32.sqrt());
+        let (near, far) = sphere.clip_planes(&Vec3::new_xyz(1.0, 2.0, 9.0), &sphere.center);
+        assert!(near > 0.0 && near < 10.0 - sphere.radius);
+        assert!(far > 10.0 + sphere.radius);
+        // a single point seen from where it is still gets a valid depth range
+        let point = BoundingSphere { center: Vec3::new(1.0), radius: 0.0 };
+        let (near, far) = point.clip_planes(&point.center, &Vec3::new(0.0));
+        assert!(near > 0.0 && far > near);
+    }
+
+    #[test]
+    fn test_fit_distance() {
+        let sphere = BoundingSphere { center: Vec3::new(0.0), radius: 1.0 };
+        let d = sphere.fit_distance(std::f32::consts::FRAC_PI_2, 1.0);
+        // the sphere touches the frustum at 1 / sin(45 deg), plus the margin
+        assert!((d - FIT_MARGIN * 2.0_f32.sqrt()).abs() < 1e-5);
+        // a narrow window constrains the horizontal field of view
+        assert!(sphere.fit_distance(std::f32::consts::FRAC_PI_2, 0.5) > d);
+    }
+}
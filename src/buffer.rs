@@ -0,0 +1,390 @@
+use crate::data::Vec3;
+use crate::shading::to_color;
+
+/// Comparison of a fragment depth with the stored one, the fragment passes when `new <op> stored`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DepthFunc
+{
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Always,
+    Never,
+}
+
+impl DepthFunc
+{
+    #[inline]
+    pub fn passes(&self, new: f32, stored: f32) -> bool
+    {
+        match self {
+            DepthFunc::Less => new < stored,
+            DepthFunc::LessEqual => new <= stored,
+            DepthFunc::Greater => new > stored,
+            DepthFunc::GreaterEqual => new >= stored,
+            DepthFunc::Always => true,
+            DepthFunc::Never => false,
+        }
+    }
+
+    /// The same test for depth growing towards the camera, as with reversed-Z
+    pub fn reversed(&self) -> DepthFunc
+    {
+        match self {
+            DepthFunc::Less => DepthFunc::Greater,
+            DepthFunc::LessEqual => DepthFunc::GreaterEqual,
+            DepthFunc::Greater => DepthFunc::Less,
+            DepthFunc::GreaterEqual => DepthFunc::LessEqual,
+            func => *func,
+        }
+    }
+
+    /// Whether some depth within `new` may pass against some depth within `stored`, both given as (min, max)
+    pub fn any_passes(&self, new: (f32, f32), stored: (f32, f32)) -> bool
+    {
+        match self {
+            DepthFunc::Less | DepthFunc::LessEqual => self.passes(new.0, stored.1),
+            DepthFunc::Greater | DepthFunc::GreaterEqual => self.passes(new.1, stored.0),
+            func => func.passes(0.0, 0.0),
+        }
+    }
+
+    /// Whether every depth within `new` passes against every depth within `stored`, both given as (min, max)
+    pub fn all_pass(&self, new: (f32, f32), stored: (f32, f32)) -> bool
+    {
+        match self {
+            DepthFunc::Less | DepthFunc::LessEqual => self.passes(new.1, stored.0),
+            DepthFunc::Greater | DepthFunc::GreaterEqual => self.passes(new.0, stored.1),
+            func => func.passes(0.0, 0.0),
+        }
+    }
+}
+
+/// How fragments are tested against and written to a depth buffer
+#[derive(Debug, Copy, Clone)]
+pub struct DepthState
+{
+    pub func: DepthFunc,
+    /// whether fragments that pass store their depth
+    pub write: bool,
+    /// depth of pixels that nothing was drawn to
+    pub clear: f32,
+}
+
+impl Default for DepthState
+{
+    /// Nearest fragments win, the buffer is cleared to the far plane of OpenGL NDC
+    fn default() -> Self
+    {
+        DepthState {
+            func: DepthFunc::Less,
+            write: true,
+            clear: 1.0,
+        }
+    }
+}
+
+/// Depth offset of a surface like `glPolygonOffset`, negative values pull it towards the camera
+///
+/// The offset is `factor` times the steepest depth slope of a triangle per pixel plus `units`
+/// times the smallest depth difference that can be resolved around it, which keeps decals and
+/// coplanar overlays from z-fighting.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct PolygonOffset
+{
+    pub factor: f32,
+    pub units: f32,
+}
+
+impl PolygonOffset
+{
+    /// The same offset for depth growing towards the camera, as with reversed-Z
+    pub fn reversed(&self) -> PolygonOffset
+    {
+        PolygonOffset {
+            factor: -self.factor,
+            units: -self.units,
+        }
+    }
+}
+
+/// Depth of every sample of every pixel, stored row by row from the bottom in one contiguous buffer
+///
+/// The samples of a pixel are next to each other. Pixel accessors `get` the first sample and
+/// `set` or `update` all of them, which is all there is without multisampling.
+///
+/// `get`, `set` and `update` panic when `x, y` is outside of the buffer, the `_unchecked`
+/// accessors skip the check for hot loops that already know their bounds.
+pub struct ZBuffer
+{
+    width: usize,
+    height: usize,
+    samples: usize,
+    depth: Vec<f32>,
+}
+
+impl ZBuffer
+{
+    pub fn new(width: usize, height: usize, init_val: f32) -> Self
+    {
+        ZBuffer {
+            width,
+            height,
+            samples: 1,
+            depth: vec![init_val; width * height],
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize
+    {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize
+    {
+        self.height
+    }
+
+    /// Number of depth samples per pixel
+    #[inline]
+    pub fn samples(&self) -> usize
+    {
+        self.samples
+    }
+
+    pub fn reset(&mut self, val: f32) {
+        self.depth.iter_mut().for_each(|depth| *depth = val);
+    }
+
+    /// Changes the size of the buffer and fills it with `val`
+    pub fn resize(&mut self, width: usize, height: usize, val: f32)
+    {
+        self.width = width;
+        self.height = height;
+        self.depth.clear();
+        self.depth.resize(width * height * self.samples, val);
+    }
+
+    /// Changes the number of samples per pixel and fills the buffer with `val`
+    pub fn set_samples(&mut self, samples: usize, val: f32)
+    {
+        assert!(samples > 0, "a pixel needs at least one sample");
+        self.samples = samples;
+        self.resize(self.width, self.height, val);
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> f32
+    {
+        self.depth[self.index(x, y)]
+    }
+
+    /// Splits the buffer into bands of `rows` rows from the bottom, the last one may be shorter
+    ///
+    /// Bands do not overlap, so a band of tiles can be written by each thread.
+    pub fn bands_mut(&mut self, rows: usize) -> Vec<DepthBand<'_>>
+    {
+        assert!(rows > 0, "a band needs at least one row");
+        let (width, samples) = (self.width, self.samples);
+        return self.depth.chunks_mut(width * samples * rows).enumerate().map(|(i, depth)| DepthBand {
+            y_min: i * rows,
+            width,
+            samples,
+            depth,
+        }).collect();
+    }
+
+    /// Index of the first sample of a pixel
+    #[inline]
+    fn index(&self, x: usize, y: usize) -> usize
+    {
+        assert!(x < self.width && y < self.height, "pixel ({}, {}) is outside of the {}x{} depth buffer",
+                x, y, self.width, self.height);
+        (y * self.width + x) * self.samples
+    }
+}
+
+/// Full-width rows of a `ZBuffer`, addressed with the coordinates of the whole buffer
+pub struct DepthBand<'a>
+{
+    y_min: usize,
+    width: usize,
+    samples: usize,
+    depth: &'a mut [f32],
+}
+
+impl<'a> DepthBand<'a>
+{
+    /// Overwrites the depth of one sample without testing it, panics outside of the band
+    #[inline]
+    pub fn set_sample(&mut self, x: usize, y: usize, sample: usize, val: f32)
+    {
+        assert!(x < self.width && y >= self.y_min && sample < self.samples,
+                "sample {} of pixel ({}, {}) is outside of the band", sample, x, y);
+        self.depth[((y - self.y_min) * self.width + x) * self.samples + sample] = val;
+    }
+}
+
+/// A color buffer that lives outside of any window
+///
+/// Every pixel keeps the float color handed to `set` next to its RGBA8 conversion, so frames can
+/// be exported both as 8-bit and as unclamped float images.
+///
+/// Pixels are addressed with device coordinates, i.e. the origin is the bottom-left corner like
+/// the fragments produced by `rasterize_triangle`, but rows are stored from top to bottom so that
+/// the bytes can be handed to image encoders as they are.
+pub struct FrameBuffer
+{
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    colors: Vec<Vec3>,
+}
+
+impl FrameBuffer
+{
+    pub fn new(width: usize, height: usize) -> Self
+    {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+            colors: vec![Vec3::new(0.0); width * height],
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize
+    {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize
+    {
+        self.height
+    }
+
+    pub fn clear(&mut self, color: Vec3)
+    {
+        let rgba = to_color(color);
+        self.pixels.chunks_exact_mut(4).for_each(|p| p.copy_from_slice(&rgba));
+        self.colors.iter_mut().for_each(|c| *c = color);
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Vec3)
+    {
+        let idx = self.index(x, y);
+        self.pixels[idx * 4..idx * 4 + 4].copy_from_slice(&to_color(color));
+        self.colors[idx] = color;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4]
+    {
+        let offset = self.index(x, y) * 4;
+        let p = &self.pixels[offset..offset + 4];
+        return [p[0], p[1], p[2], p[3]];
+    }
+
+    /// The float color of a pixel before it was clamped into RGBA8
+    pub fn get_f32(&self, x: usize, y: usize) -> Vec3
+    {
+        self.colors[self.index(x, y)]
+    }
+
+    /// Raw RGBA8 bytes, rows ordered from top to bottom
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        &self.pixels
+    }
+
+    /// Float colors, rows ordered from top to bottom
+    pub fn as_f32(&self) -> &[Vec3]
+    {
+        &self.colors
+    }
+
+    #[inline]
+    fn index(&self, x: usize, y: usize) -> usize
+    {
+        assert!(x < self.width && y < self.height, "pixel ({}, {}) is out of the {}x{} frame", x, y, self.width, self.height);
+        return (self.height - 1 - y) * self.width + x;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_buffer_rows_top_down() {
+        let mut frame = FrameBuffer::new(2, 2);
+        frame.set(1, 0, Vec3::new_rgb(1.0, 0.0, 2.0));
+        assert_eq!(frame.get(1, 0), [255, 0, 255, 255]);
+        assert_eq!(frame.get_f32(1, 0).b(), 2.0);
+        // bottom-right pixel is the last one in memory
+        assert_eq!(&frame.as_bytes()[12..16], &[255, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_depth_funcs() {
+        assert!(DepthFunc::Less.passes(0.5, 1.0));
+        assert!(!DepthFunc::Less.passes(0.5, 0.5));
+        assert!(DepthFunc::LessEqual.passes(0.5, 0.5));
+        assert!(DepthFunc::Always.passes(0.9, 0.5));
+        assert!(!DepthFunc::Never.passes(0.1, 0.5));
+        assert_eq!(DepthFunc::Less.reversed(), DepthFunc::Greater);
+        // ranges for hierarchical Z
+        assert!(DepthFunc::Less.any_passes((0.2, 0.6), (0.3, 0.5)));
+        assert!(!DepthFunc::Less.all_pass((0.2, 0.6), (0.3, 0.5)));
+        assert!(!DepthFunc::Greater.any_passes((0.2, 0.3), (0.3, 0.5)));
+        assert!(DepthFunc::GreaterEqual.all_pass((0.5, 0.6), (0.3, 0.5)));
+    }
+
+    #[test]
+    fn test_zbuffer_layout_and_bands() {
+        let mut zbuffer = ZBuffer::new(3, 5, 1.0);
+        {
+            let mut bands = zbuffer.bands_mut(2);
+            assert_eq!(bands.len(), 3);
+            bands[0].set_sample(2, 0, 0, 0.5);
+            bands[0].set_sample(0, 1, 0, 0.25);
+            bands[2].set_sample(1, 4, 0, 0.75);
+        }
+        // rows are contiguous, from the bottom
+        assert_eq!(&zbuffer.depth[..4], &[1.0, 1.0, 0.5, 0.25]);
+        assert_eq!(zbuffer.get(1, 4), 0.75);
+        zbuffer.resize(4, 2, 0.0);
+        assert_eq!((zbuffer.width(), zbuffer.height(), zbuffer.get(3, 1)), (4, 2, 0.0));
+
+        // the samples of a pixel are next to each other
+        zbuffer.set_samples(4, 1.0);
+        assert_eq!(zbuffer.depth.len(), 4 * 2 * 4);
+        {
+            let mut bands = zbuffer.bands_mut(1);
+            bands[1].set_sample(2, 1, 3, 0.5);
+        }
+        assert_eq!(zbuffer.depth[(4 + 2) * 4 + 3], 0.5);
+        assert_eq!(zbuffer.get(2, 1), 1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_depth_band_checks_bounds() {
+        let mut zbuffer = ZBuffer::new(3, 5, 1.0);
+        let mut bands = zbuffer.bands_mut(2);
+        // a pixel of the buffer, but not of this band
+        bands[1].set_sample(0, 0, 0, 0.5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zbuffer_checks_bounds() {
+        let zbuffer = ZBuffer::new(3, 5, 1.0);
+        // in range of the storage, but not a pixel of the buffer
+        zbuffer.get(3, 0);
+    }
+}
@@ -0,0 +1,708 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind, SubCommand};
+
+use crate::antialiasing::Downsample;
+use crate::buffer::DepthFunc;
+use crate::culling::{CullMode, Winding};
+use crate::data::{Cross, Length, Minus, Vec3};
+use crate::mesh::NormalMode;
+use crate::renderer::DepthRemap;
+use crate::shader::ShadingMode;
+use crate::shading::{Light, LightingModel, Msaa};
+use crate::texture::{Filter, MAX_ANISOTROPY, Wrap};
+
+/// What the user asked the binary to do
+pub enum Command
+{
+    /// Open a window and render interactively
+    View(Options),
+    /// Render a single frame and write it to `output`, and its depth to `depth_output`
+    Render {
+        options: Options,
+        output: PathBuf,
+        depth_output: Option<PathBuf>,
+    },
+}
+
+/// Scene, camera and shading parameters shared by all subcommands
+pub struct Options
+{
+    pub model_path: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    /// Object space point moved to the world origin, defaults to the center of the bounding box
+    pub object_center: Option<Vec3>,
+    /// Defaults to the radius of the bounding sphere
+    pub bounding_radius: Option<f32>,
+    /// Camera position, defaults to a point on +z that sees the whole object
+    pub eye: Option<Vec3>,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub fov_y_deg: f32,
+    /// Near and far planes, default to planes fitted to the bounding sphere
+    pub near: Option<f32>,
+    pub far: Option<f32>,
+    pub infinite_far: bool,
+    pub reversed_z: bool,
+    pub depth_func: DepthFunc,
+    pub depth_write: bool,
+    pub depth_remap: DepthRemap,
+    /// Distance shown white by linear depth images, the far plane if not given
+    pub depth_far: Option<f32>,
+    /// Samples per pixel of multisample anti-aliasing
+    pub msaa: Msaa,
+    /// Supersampling factor along each axis and the filter applied to go back to the frame size
+    pub ssaa_factor: usize,
+    pub ssaa_filter: Downsample,
+    pub fxaa: bool,
+    pub shading: ShadingMode,
+    pub normal_mode: NormalMode,
+    pub cull_mode: CullMode,
+    pub front_face: Winding,
+    pub texture_filter: Filter,
+    pub texture_wrap: Wrap,
+    pub max_anisotropy: u32,
+    /// lights in world coordinates
+    pub lights: Vec<Light>,
+    pub ambient: Vec3,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub shininess: f32,
+    /// physically based parameters of meshes without an MTL material
+    pub metallic: Option<f32>,
+    pub roughness: Option<f32>,
+    pub lighting: LightingModel,
+}
+
+pub fn parse_from<I, T>(args: I) -> Result<Command, clap::Error>
+    where I: IntoIterator<Item=T>, T: Into<OsString> + Clone
+{
+    let matches = app().get_matches_from_safe(args)?;
+    let command = match matches.subcommand() {
+        ("view", Some(sub)) => Command::View(options(sub)),
+        ("render", Some(sub)) => Command::Render {
+            options: options(sub),
+            output: PathBuf::from(sub.value_of("output").unwrap()),
+            depth_output: sub.value_of("depth-output").map(PathBuf::from),
+        },
+        _ => unreachable!("a subcommand is required"),
+    };
+    // the viewer can always show depth
+    let (options, depth_images) = match &command {
+        Command::View(options) => (options, true),
+        Command::Render { options, depth_output, .. } => (options, depth_output.is_some()),
+    };
+    check_camera(options).map_err(|e| clap::Error::with_description(&e, ErrorKind::ArgumentConflict))?;
+    if depth_images && options.infinite_far && options.depth_remap == DepthRemap::Linear && options.depth_far.is_none()
+    {
+        return Err(clap::Error::with_description("linear depth images of an infinite far plane need --depth-far \
+                                                  or --depth-remap nonlinear", ErrorKind::MissingRequiredArgument));
+    }
+    return Ok(command);
+}
+
+/// Rejects cameras whose orientation is undefined, the default eye looks down the z axis at the target
+fn check_camera(options: &Options) -> Result<(), String>
+{
+    let view = match options.eye {
+        Some(eye) => eye._minus(&options.target),
+        None => Vec3::new_xyz(0.0, 0.0, 1.0),
+    };
+    if view.get_length() == 0.0
+    {
+        return Err("the camera eye should not be at its target".to_string());
+    }
+    if options.up.cross(&view).get_length() <= 1e-6 * options.up.get_length() * view.get_length()
+    {
+        return Err("the up direction should not be parallel to the view direction".to_string());
+    }
+    return Ok(());
+}
+
+fn app() -> App<'static, 'static>
+{
+    App::new("rusterizer")
+        .about("A simple software rasterizer")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(SubCommand::with_name("view")
+            .about("Opens a window to orbit around a model")
+            .args(&common_args()))
+        .subcommand(SubCommand::with_name("render")
+            .about("Renders one frame of a model to an image file without opening a window")
+            .args(&common_args())
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .required(true)
+                .value_name("FILE")
+                .help("Output image, the format is inferred from the extension (png, ppm or pfm)"))
+            .arg(Arg::with_name("depth-output")
+                .long("depth-output")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Also write the depth buffer as a grayscale image (png or pfm)")))
+}
+
+fn common_args() -> Vec<Arg<'static, 'static>>
+{
+    vec![
+        Arg::with_name("model")
+            .required(true)
+            .value_name("MODEL")
+            .help("Path to an OBJ file"),
+        Arg::with_name("width")
+            .long("width")
+            .takes_value(true)
+            .default_value("600")
+            .validator(validate_positive::<usize>)
+            .help("Image width in pixels"),
+        Arg::with_name("height")
+            .long("height")
+            .takes_value(true)
+            .default_value("600")
+            .validator(validate_positive::<usize>)
+            .help("Image height in pixels"),
+        Arg::with_name("center")
+            .long("center")
+            .takes_value(true)
+            .value_name("X,Y,Z")
+            .allow_hyphen_values(true)
+            .validator(validate_vec3)
+            .help("Object space point moved to the world origin [default: center of the bounding box]"),
+        Arg::with_name("radius")
+            .long("radius")
+            .takes_value(true)
+            .validator(validate_positive_float)
+            .help("Bounding radius of the object, used to place the camera and the clipping planes \
+                   [default: radius of the bounding sphere]"),
+        Arg::with_name("eye")
+            .long("eye")
+            .takes_value(true)
+            .value_name("X,Y,Z")
+            .allow_hyphen_values(true)
+            .validator(validate_vec3)
+            .help("Camera position in world coordinates [default: on +z, far enough to see the whole object]"),
+        vec3_arg("target", "0,0,0", "Point the camera looks at"),
+        vec3_arg("up", "0,1,0", "Up direction of the camera, not parallel to the view direction")
+            .validator(validate_direction),
+        Arg::with_name("fov")
+            .long("fov")
+            .takes_value(true)
+            .default_value("112.5")
+            .validator(validate_fov)
+            .help("Vertical field of view in degrees, between 0 and 180"),
+        Arg::with_name("near")
+            .long("near")
+            .takes_value(true)
+            .validator(validate_positive_float)
+            .help("Near clipping plane distance [default: fitted to the bounding sphere]"),
+        Arg::with_name("far")
+            .long("far")
+            .takes_value(true)
+            .validator(validate_positive_float)
+            .help("Far clipping plane distance [default: fitted to the bounding sphere]"),
+        Arg::with_name("infinite-far")
+            .long("infinite-far")
+            .conflicts_with("far")
+            .help("Put the far clipping plane at infinity"),
+        Arg::with_name("reversed-z")
+            .long("reversed-z")
+            .help("Map the near plane to depth 1 and the far plane to 0, for more precision far away"),
+        Arg::with_name("depth-func")
+            .long("depth-func")
+            .takes_value(true)
+            .possible_values(&["less", "lequal", "greater", "gequal", "always", "never"])
+            .case_insensitive(true)
+            .default_value("less")
+            .help("Depth test, where less keeps the nearest fragments (mirrored with --reversed-z)"),
+        Arg::with_name("no-depth-write")
+            .long("no-depth-write")
+            .help("Test fragments against the depth buffer without writing to it"),
+        Arg::with_name("depth-remap")
+            .long("depth-remap")
+            .takes_value(true)
+            .possible_values(&["linear", "nonlinear"])
+            .case_insensitive(true)
+            .default_value("linear")
+            .help("How depth images map depths between the near and far planes to gray levels, linear in the \
+                   distance or as stored in the depth buffer (nonlinear)"),
+        Arg::with_name("depth-far")
+            .long("depth-far")
+            .takes_value(true)
+            .validator(validate_positive_float)
+            .help("Distance shown white in linear depth images, needed with --infinite-far [default: the far plane]"),
+        Arg::with_name("msaa")
+            .long("msaa")
+            .takes_value(true)
+            .possible_values(&["1", "2", "4", "8"])
+            .default_value("1")
+            .help("Samples per pixel of multisample anti-aliasing, 1 turns it off"),
+        Arg::with_name("ssaa")
+            .long("ssaa")
+            .takes_value(true)
+            .possible_values(&["1", "2", "3", "4"])
+            .default_value("1")
+            .help("Render at this many times the width and height and filter down, 1 turns it off"),
+        Arg::with_name("ssaa-filter")
+            .long("ssaa-filter")
+            .takes_value(true)
+            .possible_values(&["box", "lanczos"])
+            .case_insensitive(true)
+            .default_value("box")
+            .help("Filter of supersampled frames, lanczos is sharper than box"),
+        Arg::with_name("fxaa")
+            .long("fxaa")
+            .help("Smooth edges of the final frame with FXAA"),
+        Arg::with_name("shading")
+            .long("shading")
+            .takes_value(true)
+            .possible_values(&["flat", "gouraud", "phong"])
+            .case_insensitive(true)
+            .default_value("gouraud")
+            .help("Shading mode, flat lights each triangle once with the normal of its plane"),
+        Arg::with_name("normals")
+            .long("normals")
+            .takes_value(true)
+            .possible_values(&["auto", "smooth", "flat"])
+            .case_insensitive(true)
+            .default_value("auto")
+            .help("Vertex normals: authored ones when the model has them (auto), averaged over adjacent faces \
+                   (smooth) or one per face (flat)"),
+        Arg::with_name("cull")
+            .long("cull")
+            .takes_value(true)
+            .possible_values(&["none", "back", "front"])
+            .case_insensitive(true)
+            .default_value("back")
+            .help("Faces dropped before rasterization"),
+        Arg::with_name("front-face")
+            .long("front-face")
+            .takes_value(true)
+            .possible_values(&["ccw", "cw"])
+            .case_insensitive(true)
+            .default_value("ccw")
+            .help("Winding order of front faces as seen from the camera"),
+        Arg::with_name("texture-filter")
+            .long("texture-filter")
+            .takes_value(true)
+            .possible_values(&["nearest", "bilinear", "trilinear"])
+            .case_insensitive(true)
+            .default_value("trilinear")
+            .help("How textures are sampled between texels, trilinear also blends mip levels"),
+        Arg::with_name("texture-wrap")
+            .long("texture-wrap")
+            .takes_value(true)
+            .possible_values(&["repeat", "clamp"])
+            .case_insensitive(true)
+            .default_value("repeat")
+            .help("How texture coordinates outside of [0, 1] are handled"),
+        Arg::with_name("anisotropy")
+            .long("anisotropy")
+            .takes_value(true)
+            .default_value("1")
+            .validator(validate_anisotropy)
+            .help("Most samples per fragment for anisotropic texture filtering, 1 turns it off (trilinear only)"),
+        Arg::with_name("light")
+            .long("light")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("LIGHT")
+            .allow_hyphen_values(true)
+            .default_value("200,200,200:intensity=0.7")
+            .validator(validate_light)
+            .help("A light in world coordinates, repeat for more: X,Y,Z or point:X,Y,Z for a point light, \
+                   directional:DX,DY,DZ, or spot:X,Y,Z:DX,DY,DZ:INNER,OUTER with half angles in degrees, \
+                   optionally followed by :color=R,G,B, :intensity=I or :attenuation=CONSTANT,LINEAR,QUADRATIC"),
+        vec3_arg("ambient", "0.1,0.1,0.2", "Ambient color of meshes without an MTL material"),
+        vec3_arg("diffuse", "0.5,0.5,0.6", "Diffuse color of meshes without an MTL material"),
+        vec3_arg("specular", "1,1,1", "Specular color of meshes without an MTL material"),
+        Arg::with_name("shininess")
+            .long("shininess")
+            .takes_value(true)
+            .default_value("16")
+            .validator(validate_positive_float)
+            .help("Specular exponent of meshes without an MTL material"),
+        Arg::with_name("metallic")
+            .long("metallic")
+            .takes_value(true)
+            .validator(validate_unit)
+            .help("Metalness from 0 to 1 of meshes without an MTL material, for PBR lighting [default: 0]"),
+        Arg::with_name("roughness")
+            .long("roughness")
+            .takes_value(true)
+            .validator(validate_unit)
+            .help("Roughness from 0 to 1 of meshes without an MTL material, for PBR lighting \
+                   [default: matching the shininess]"),
+        Arg::with_name("lighting")
+            .long("lighting")
+            .takes_value(true)
+            .possible_values(&["phong", "blinn-phong", "pbr"])
+            .case_insensitive(true)
+            .default_value("phong")
+            .help("Lighting model, pbr uses the metallic-roughness parameters of the materials"),
+    ]
+}
+
+fn vec3_arg(name: &'static str, default: &'static str, help: &'static str) -> Arg<'static, 'static>
+{
+    Arg::with_name(name)
+        .long(name)
+        .takes_value(true)
+        .value_name("X,Y,Z")
+        .allow_hyphen_values(true)
+        .default_value(default)
+        .validator(validate_vec3)
+        .help(help)
+}
+
+fn options(matches: &ArgMatches) -> Options
+{
+    // every value below has been checked by its validator already
+    Options {
+        model_path: PathBuf::from(matches.value_of("model").unwrap()),
+        width: matches.value_of("width").unwrap().parse().unwrap(),
+        height: matches.value_of("height").unwrap().parse().unwrap(),
+        object_center: matches.value_of("center").map(|s| parse_vec3(s).unwrap()),
+        bounding_radius: matches.value_of("radius").map(|s| s.parse().unwrap()),
+        eye: matches.value_of("eye").map(|s| parse_vec3(s).unwrap()),
+        target: vec3_of(matches, "target"),
+        up: vec3_of(matches, "up"),
+        fov_y_deg: matches.value_of("fov").unwrap().parse().unwrap(),
+        near: matches.value_of("near").map(|s| s.parse().unwrap()),
+        far: matches.value_of("far").map(|s| s.parse().unwrap()),
+        infinite_far: matches.is_present("infinite-far"),
+        reversed_z: matches.is_present("reversed-z"),
+        depth_func: match matches.value_of("depth-func").unwrap().to_ascii_lowercase().as_str() {
+            "lequal" => DepthFunc::LessEqual,
+            "greater" => DepthFunc::Greater,
+            "gequal" => DepthFunc::GreaterEqual,
+            "always" => DepthFunc::Always,
+            "never" => DepthFunc::Never,
+            _ => DepthFunc::Less,
+        },
+        depth_write: !matches.is_present("no-depth-write"),
+        depth_far: matches.value_of("depth-far").map(|s| s.parse().unwrap()),
+        depth_remap: match matches.value_of("depth-remap").unwrap().to_ascii_lowercase().as_str() {
+            "nonlinear" => DepthRemap::NonLinear,
+            _ => DepthRemap::Linear,
+        },
+        msaa: Msaa::from_samples(matches.value_of("msaa").unwrap().parse().unwrap()).unwrap(),
+        ssaa_factor: matches.value_of("ssaa").unwrap().parse().unwrap(),
+        ssaa_filter: match matches.value_of("ssaa-filter").unwrap().to_ascii_lowercase().as_str() {
+            "lanczos" => Downsample::Lanczos,
+            _ => Downsample::Box,
+        },
+        fxaa: matches.is_present("fxaa"),
+        shading: match matches.value_of("shading").unwrap().to_ascii_lowercase().as_str() {
+            "flat" => ShadingMode::Flat,
+            "phong" => ShadingMode::Phong,
+            _ => ShadingMode::Gouraud,
+        },
+        normal_mode: match matches.value_of("normals").unwrap().to_ascii_lowercase().as_str() {
+            "smooth" => NormalMode::Smooth,
+            "flat" => NormalMode::Flat,
+            _ => NormalMode::Auto,
+        },
+        cull_mode: match matches.value_of("cull").unwrap().to_ascii_lowercase().as_str() {
+            "none" => CullMode::None,
+            "front" => CullMode::Front,
+            _ => CullMode::Back,
+        },
+        front_face: match matches.value_of("front-face").unwrap().to_ascii_lowercase().as_str() {
+            "cw" => Winding::Cw,
+            _ => Winding::Ccw,
+        },
+        texture_filter: match matches.value_of("texture-filter").unwrap().to_ascii_lowercase().as_str() {
+            "nearest" => Filter::Nearest,
+            "bilinear" => Filter::Bilinear,
+            _ => Filter::Trilinear,
+        },
+        texture_wrap: match matches.value_of("texture-wrap").unwrap().to_ascii_lowercase().as_str() {
+            "clamp" => Wrap::Clamp,
+            _ => Wrap::Repeat,
+        },
+        max_anisotropy: matches.value_of("anisotropy").unwrap().parse().unwrap(),
+        lights: matches.values_of("light").unwrap().map(|s| parse_light(s).unwrap()).collect(),
+        ambient: vec3_of(matches, "ambient"),
+        diffuse: vec3_of(matches, "diffuse"),
+        specular: vec3_of(matches, "specular"),
+        shininess: matches.value_of("shininess").unwrap().parse().unwrap(),
+        metallic: matches.value_of("metallic").map(|s| s.parse().unwrap()),
+        roughness: matches.value_of("roughness").map(|s| s.parse().unwrap()),
+        lighting: match matches.value_of("lighting").unwrap().to_ascii_lowercase().as_str() {
+            "blinn-phong" => LightingModel::BlinnPhong,
+            "pbr" => LightingModel::Pbr,
+            _ => LightingModel::Phong,
+        },
+    }
+}
+
+fn vec3_of(matches: &ArgMatches, name: &str) -> Vec3
+{
+    parse_vec3(matches.value_of(name).unwrap()).unwrap()
+}
+
+/// Parses a comma separated triple such as `1,2.5,-3`
+pub fn parse_vec3(s: &str) -> Result<Vec3, String>
+{
+    let xyz = parse_floats(s, 3)?;
+    return Ok(Vec3::new_xyz(xyz[0], xyz[1], xyz[2]));
+}
+
+/// Parses `n` comma separated finite numbers
+fn parse_floats(s: &str, n: usize) -> Result<Vec<f32>, String>
+{
+    let components: Vec<&str> = s.split(',').map(|c| c.trim()).collect();
+    if components.len() != n
+    {
+        return Err(format!("expected {} comma separated numbers, got \"{}\"", n, s));
+    }
+    let mut values = vec![0.0; n];
+    for (v, c) in values.iter_mut().zip(components.iter())
+    {
+        *v = c.parse::<f32>().map_err(|_| format!("\"{}\" is not a number", c))?;
+        if !v.is_finite()
+        {
+            return Err(format!("\"{}\" is not a finite number", c));
+        }
+    }
+    return Ok(values);
+}
+
+/// Parses a light such as `200,200,200`, `point:0,5,0`, `directional:-1,-1,-1` or
+/// `spot:0,5,0:0,-1,0:20,30`, followed by `:color=R,G,B`, `:intensity=I` or `:attenuation=C,L,Q`
+pub fn parse_light(s: &str) -> Result<Light, String>
+{
+    let parts: Vec<&str> = s.split(':').map(|p| p.trim()).collect();
+    let kind = parts[0].to_ascii_lowercase();
+    // a bare position is a point light
+    let (kind, parts) = match kind.as_str() {
+        "point" | "directional" | "spot" => (kind.as_str(), &parts[1..]),
+        _ => ("point", &parts[..]),
+    };
+    let settings_start = parts.iter().position(|p| p.contains('=')).unwrap_or(parts.len());
+    let (values, settings) = parts.split_at(settings_start);
+    let mut light = match (kind, values) {
+        ("point", [position]) => Light::point(parse_vec3(position)?),
+        ("directional", [direction]) => Light::directional(parse_direction(direction)?),
+        ("spot", [position, direction, angles]) => {
+            let angles = parse_floats(angles, 2)?;
+            if angles[0] < 0.0 || angles[0] > angles[1] || angles[1] >= 90.0
+            {
+                return Err(format!("the half angles of a spot light should satisfy 0 <= inner <= outer < 90, got \"{}\"",
+                                   s));
+            }
+            Light::spot(parse_vec3(position)?, parse_direction(direction)?, angles[0].to_radians(), angles[1].to_radians())
+        }
+        _ => return Err(format!("\"{}\" is not a light, expected X,Y,Z, point:X,Y,Z, directional:DX,DY,DZ or \
+                                 spot:X,Y,Z:DX,DY,DZ:INNER,OUTER", s)),
+    };
+    for setting in settings.iter()
+    {
+        let (key, value) = setting.split_at(setting.find('=').unwrap_or(setting.len()));
+        let value = value.trim_start_matches('=');
+        match key.trim().to_ascii_lowercase().as_str() {
+            "color" => light.color = parse_vec3(value)?,
+            "intensity" => light.intensity = match value.parse::<f32>() {
+                Ok(v) if v >= 0.0 && v.is_finite() => v,
+                _ => return Err(format!("the intensity should be a number of at least zero, got \"{}\"", value)),
+            },
+            "attenuation" => {
+                let attenuation = parse_vec3(value)?;
+                if attenuation.x() < 0.0 || attenuation.y() < 0.0 || attenuation.z() < 0.0
+                    || attenuation.x() + attenuation.y() + attenuation.z() == 0.0
+                {
+                    return Err(format!("attenuation factors should not be negative or all zero, got \"{}\"", value));
+                }
+                light.attenuation = attenuation;
+            }
+            _ => return Err(format!("\"{}\" is not a light setting, expected color, intensity or attenuation", setting)),
+        }
+    }
+    return Ok(light);
+}
+
+fn parse_direction(s: &str) -> Result<Vec3, String>
+{
+    let direction = parse_vec3(s)?;
+    if direction.x() == 0.0 && direction.y() == 0.0 && direction.z() == 0.0
+    {
+        return Err("a direction should not be zero".to_string());
+    }
+    return Ok(direction);
+}
+
+fn validate_vec3(s: String) -> Result<(), String>
+{
+    parse_vec3(&s).map(|_| ())
+}
+
+fn validate_direction(s: String) -> Result<(), String>
+{
+    parse_direction(&s).map(|_| ())
+}
+
+fn validate_light(s: String) -> Result<(), String>
+{
+    parse_light(&s).map(|_| ())
+}
+
+fn validate_unit(s: String) -> Result<(), String>
+{
+    match s.parse::<f32>() {
+        Ok(v) if v >= 0.0 && v <= 1.0 => Ok(()),
+        Ok(_) => Err(format!("{} should be between 0 and 1", s)),
+        Err(_) => Err(format!("\"{}\" is not a valid number", s)),
+    }
+}
+
+fn validate_anisotropy(s: String) -> Result<(), String>
+{
+    validate_positive::<u32>(s.clone())?;
+    if s.parse::<u32>().unwrap() > MAX_ANISOTROPY
+    {
+        return Err(format!("{} is larger than the maximum of {}", s, MAX_ANISOTROPY));
+    }
+    return Ok(());
+}
+
+fn validate_fov(s: String) -> Result<(), String>
+{
+    match s.parse::<f32>() {
+        Ok(v) if v > 0.0 && v < 180.0 => Ok(()),
+        Ok(_) => Err(format!("{} should be between 0 and 180 degrees", s)),
+        Err(_) => Err(format!("\"{}\" is not a valid number", s)),
+    }
+}
+
+fn validate_positive_float(s: String) -> Result<(), String>
+{
+    match s.parse::<f32>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(()),
+        Ok(_) => Err(format!("{} should be a finite number greater than zero", s)),
+        Err(_) => Err(format!("\"{}\" is not a valid number", s)),
+    }
+}
+
+fn validate_positive<T>(s: String) -> Result<(), String>
+    where T: FromStr + PartialOrd + Default
+{
+    match s.parse::<T>() {
+        Ok(v) if v > T::default() => Ok(()),
+        Ok(_) => Err(format!("{} should be greater than zero", s)),
+        Err(_) => Err(format!("\"{}\" is not a valid number", s)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::shading::LightKind;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_render() {
+        let command = parse_from(vec!["rusterizer", "render", "model.obj", "-o", "out.png",
+                                      "--width", "320", "--eye", "1,-2,3", "--shading", "phong",
+                                      "--normals", "Flat", "--texture-filter", "nearest",
+                                      "--cull", "none", "--reversed-z", "--depth-func", "LEqual",
+                                      "--depth-output", "depth.pfm", "--msaa", "4",
+                                      "--ssaa", "2", "--ssaa-filter", "Lanczos", "--lighting", "PBR",
+                                      "--roughness", "0.5"]).unwrap();
+        match command {
+            Command::Render { options, output, depth_output } => {
+                assert_eq!(output, PathBuf::from("out.png"));
+                assert_eq!(depth_output, Some(PathBuf::from("depth.pfm")));
+                assert_eq!(options.depth_remap, DepthRemap::Linear);
+                assert_eq!(options.model_path, PathBuf::from("model.obj"));
+                assert_eq!(options.width, 320);
+                assert_eq!(options.height, 600);
+                assert_eq!(options.eye.unwrap().y(), -2.0);
+                assert!(options.object_center.is_none());
+                assert!(options.far.is_none());
+                assert_eq!(options.shading, ShadingMode::Phong);
+                assert_eq!(options.normal_mode, NormalMode::Flat);
+                assert_eq!(options.texture_filter, Filter::Nearest);
+                assert_eq!(options.texture_wrap, Wrap::Repeat);
+                assert_eq!(options.max_anisotropy, 1);
+                assert_eq!(options.cull_mode, CullMode::None);
+                assert_eq!(options.front_face, Winding::Ccw);
+                assert!(options.reversed_z && !options.infinite_far);
+                assert_eq!(options.depth_func, DepthFunc::LessEqual);
+                assert!(options.depth_write);
+                assert_eq!(options.msaa, Msaa::X4);
+                assert_eq!((options.ssaa_factor, options.ssaa_filter, options.fxaa), (2, Downsample::Lanczos, false));
+                assert_eq!((options.lighting, options.metallic, options.roughness), (LightingModel::Pbr, None, Some(0.5)));
+            }
+            Command::View(_) => panic!("expected the render subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_reject_bad_arguments() {
+        assert!(parse_from(vec!["rusterizer", "render", "model.obj"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--eye", "1,2"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--width", "0"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--shading", "toon"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--normals", "sharp"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--anisotropy", "32"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--msaa", "3"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--lighting", "toon"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--metallic", "1.5"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--ssaa", "0"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--far", "10", "--infinite-far"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--infinite-far"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--near", "inf"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--fov", "180"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--up", "0,0,0"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--up", "0,0,-2"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--eye", "1,2,3", "--target", "1,2,3"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--eye", "0,5,0", "--up", "0,1,0"]).is_err());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--eye", "0,5,0", "--up", "0,0,-1"]).is_ok());
+        assert!(parse_from(vec!["rusterizer", "view", "model.obj", "--infinite-far", "--depth-far", "10"]).is_ok());
+        assert!(parse_from(vec!["rusterizer", "render", "model.obj", "-o", "a.png", "--infinite-far"]).is_ok());
+        assert!(parse_from(vec!["rusterizer", "render", "model.obj", "-o", "a.png", "--infinite-far",
+                                "--depth-output", "d.png"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_lights() {
+        let command = parse_from(vec!["rusterizer", "view", "model.obj", "--light", "0,5,0:color=1,0,0",
+                                      "--light", "directional:0,-2,0:intensity=0.5",
+                                      "--light", "Spot:0,5,0:0,-1,0:20,30:attenuation=1,0.1,0"]).unwrap();
+        let lights = match command {
+            Command::View(options) => options.lights,
+            _ => panic!("expected the view subcommand"),
+        };
+        assert_eq!(lights.len(), 3);
+        assert_eq!((lights[0].kind, lights[0].position.y(), lights[0].color.g()), (LightKind::Point, 5.0, 0.0));
+        assert_eq!((lights[1].kind, lights[1].direction.y(), lights[1].intensity), (LightKind::Directional, -1.0, 0.5));
+        assert_eq!(lights[2].kind, LightKind::Spot { inner_angle: 20f32.to_radians(), outer_angle: 30f32.to_radians() });
+        assert_eq!(lights[2].attenuation.y(), 0.1);
+        // the default light
+        match parse_from(vec!["rusterizer", "view", "model.obj"]).unwrap() {
+            Command::View(options) => assert_eq!((options.lights.len(), options.lights[0].intensity), (1, 0.7)),
+            _ => panic!("expected the view subcommand"),
+        }
+        assert!(parse_light("directional:0,0,0").is_err());
+        assert!(parse_light("spot:0,5,0:0,-1,0:30,20").is_err());
+        assert!(parse_light("point:0,5,0:intensity=-1").is_err());
+        assert!(parse_light("point:0,5,0:radius=1").is_err());
+        assert!(parse_light("area:0,5,0").is_err());
+    }
+
+    #[test]
+    fn test_parse_vec3() {
+        let v = parse_vec3("1, 2.5,-3").unwrap();
+        assert_eq!((v.x(), v.y(), v.z()), (1.0, 2.5, -3.0));
+        assert!(parse_vec3("1,2,x").is_err());
+        assert!(parse_vec3("1,2,3,4").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::data::{Add, ScalarMul, Vec4};
+use crate::shader::Varyings;
+
+/// A polygon corner in clip space, carrying the attributes that are interpolated across it
+#[derive(Debug, Copy, Clone)]
+pub struct ClipVertex
+{
+    /// position after the projection, before the division by w
+    pub position_cc: Vec4,
+    pub varyings: Varyings,
+}
+
+impl ClipVertex
+{
+    /// Point at `t` on the segment from `self` to `other`
+    ///
+    /// Every attribute is linear in clip space, so they are all blended with the same weights.
+    fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex
+    {
+        ClipVertex {
+            position_cc: self.position_cc.scalar_mul(1.0 - t)._add(&other.position_cc.scalar_mul(t)),
+            varyings: self.varyings.scalar_mul(1.0 - t)._add(&other.varyings.scalar_mul(t)),
+        }
+    }
+}
+
+/// Range of NDC depth inside of the view frustum
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DepthRange
+{
+    /// -w <= z <= w in clip space, like OpenGL
+    MinusOneToOne,
+    /// 0 <= z <= w in clip space, used by reversed-Z
+    ZeroToOne,
+}
+
+/// The six planes of the view frustum in clip space, -w <= x, y <= w and z within the `DepthRange`
+///
+/// With reversed-Z, the near and far planes swap places.
+const FRUSTUM_PLANES: [Plane; 6] = [
+    Plane::Left, Plane::Right, Plane::Bottom, Plane::Top, Plane::Near, Plane::Far
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Plane
+{
+    Left,
+    Right,
+    Bottom,
+    Top,
+    Near,
+    Far,
+}
+
+impl Plane
+{
+    /// Signed distance-like value that is non-negative on the inner side of the plane
+    #[inline]
+    fn distance(&self, p: &Vec4, depth_range: DepthRange) -> f32
+    {
+        match self {
+            Plane::Near if depth_range == DepthRange::ZeroToOne => p.z(),
+            Plane::Left => p.w() + p.x(),
+            Plane::Right => p.w() - p.x(),
+            Plane::Bottom => p.w() + p.y(),
+            Plane::Top => p.w() - p.y(),
+            Plane::Near => p.w() + p.z(),
+            Plane::Far => p.w() - p.z(),
+        }
+    }
+
+    #[inline]
+    fn bit(&self) -> u8
+    {
+        1 << (*self as u8)
+    }
+}
+
+/// Bit set of the frustum planes that `p` is outside of
+#[inline]
+pub fn outcode(p: &Vec4, depth_range: DepthRange) -> u8
+{
+    let mut code = 0;
+    for plane in FRUSTUM_PLANES.iter()
+    {
+        if plane.distance(p, depth_range) < 0.0
+        {
+            code |= plane.bit();
+        }
+    }
+    return code;
+}
+
+/// Clips a triangle against the view frustum with the Sutherland–Hodgman algorithm
+///
+/// `codes` are the `outcode`s of the corners, which culling has computed already. Returns the
+/// corners of the clipped convex polygon, which is empty when the triangle lies outside of the
+/// frustum. Triangles fully outside of one plane are rejected and triangles fully inside are
+/// returned as they are, without clipping.
+pub fn clip_triangle(triangle: &[ClipVertex; 3], codes: [u8; 3], depth_range: DepthRange) -> Vec<ClipVertex>
+{
+    if codes[0] & codes[1] & codes[2] != 0
+    {
+        return Vec::new();
+    }
+    let mut polygon = triangle.to_vec();
+    let crossed = codes[0] | codes[1] | codes[2];
+    for plane in FRUSTUM_PLANES.iter()
+    {
+        if crossed & plane.bit() == 0
+        {
+            continue;
+        }
+        polygon = clip_polygon(&polygon, plane, depth_range);
+        if polygon.len() < 3
+        {
+            return Vec::new();
+        }
+    }
+    return polygon;
+}
+
+/// Keeps the part of a convex polygon on the inner side of `plane`
+fn clip_polygon(polygon: &Vec<ClipVertex>, plane: &Plane, depth_range: DepthRange) -> Vec<ClipVertex>
+{
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, current) in polygon.iter().enumerate()
+    {
+        let next = &polygon[(i + 1) % polygon.len()];
+        let d_current = plane.distance(&current.position_cc, depth_range);
+        let d_next = plane.distance(&next.position_cc, depth_range);
+        if d_current >= 0.0
+        {
+            clipped.push(*current);
+        }
+        // the edge crosses the plane, strictly, so the division is safe
+        if (d_current >= 0.0) != (d_next >= 0.0)
+        {
+            let t = d_current / (d_current - d_next);
+            clipped.push(current.lerp(next, t));
+        }
+    }
+    return clipped;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::Vec2;
+
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32, w: f32) -> ClipVertex
+    {
+        let mut varyings = Varyings::new();
+        varyings.push_vec4(&Vec4::new_xyzw(x, y, z, w)).unwrap();
+        varyings.push_texcoord(&Vec2::new_uv(x, y));
+        ClipVertex {
+            position_cc: Vec4::new_xyzw(x, y, z, w),
+            varyings,
+        }
+    }
+
+    fn clip(triangle: &[ClipVertex; 3], depth_range: DepthRange) -> Vec<ClipVertex>
+    {
+        let codes = [outcode(&triangle[0].position_cc, depth_range), outcode(&triangle[1].position_cc, depth_range),
+            outcode(&triangle[2].position_cc, depth_range)];
+        return clip_triangle(triangle, codes, depth_range);
+    }
+
+    /// Intersections may land a few ulps outside of the plane they were clipped against
+    fn inside(p: &Vec4, depth_range: DepthRange) -> bool
+    {
+        FRUSTUM_PLANES.iter().all(|plane| plane.distance(p, depth_range) > -1e-5)
+    }
+
+    #[test]
+    fn test_trivial_accept_and_reject() {
+        let inside = [vertex(0.0, 0.0, 0.0, 1.0), vertex(0.5, 0.0, 0.0, 1.0), vertex(0.0, 0.5, 0.0, 1.0)];
+        assert_eq!(clip(&inside, DepthRange::MinusOneToOne).len(), 3);
+        let right = [vertex(2.0, 0.0, 0.0, 1.0), vertex(3.0, 0.0, 0.0, 1.0), vertex(2.0, 0.5, 0.0, 1.0)];
+        assert!(clip(&right, DepthRange::MinusOneToOne).is_empty());
+        // behind the camera, w is negative and every point fails the near plane
+        let behind = [vertex(0.0, 0.0, -2.0, -1.0), vertex(0.5, 0.0, -2.0, -1.0), vertex(0.0, 0.5, -2.0, -1.0)];
+        assert!(clip(&behind, DepthRange::MinusOneToOne).is_empty());
+    }
+
+    #[test]
+    fn test_clip_against_near_plane() {
+        // one corner behind the camera turns the triangle into a quadrilateral
+        let triangle = [vertex(0.0, 0.0, 0.0, 1.0), vertex(0.5, 0.0, 0.0, 1.0), vertex(0.0, 0.0, -2.0, -1.0)];
+        let polygon = clip(&triangle, DepthRange::MinusOneToOne);
+        assert_eq!(polygon.len(), 4);
+        for v in polygon.iter()
+        {
+            assert!(inside(&v.position_cc, DepthRange::MinusOneToOne));
+            assert!(v.position_cc.w() > 0.0);
+        }
+        // attributes follow the positions
+        let on_plane = polygon.iter().find(|v| (v.position_cc.z() + v.position_cc.w()).abs() < 1e-6).unwrap();
+        assert_eq!(on_plane.varyings.vec2(1).u(), on_plane.position_cc.x());
+        assert_eq!(on_plane.varyings.vec4(0).w(), on_plane.position_cc.w());
+    }
+
+    #[test]
+    fn test_clip_against_several_planes() {
+        // a large triangle covering the whole viewport is cut by the four side planes
+        let triangle = [vertex(-10.0, -10.0, 0.0, 1.0), vertex(10.0, -10.0, 0.0, 1.0), vertex(0.0, 10.0, 0.0, 1.0)];
+        let polygon = clip(&triangle, DepthRange::MinusOneToOne);
+        assert!(polygon.len() >= 4);
+        for v in polygon.iter()
+        {
+            assert!(inside(&v.position_cc, DepthRange::MinusOneToOne));
+        }
+    }
+
+    #[test]
+    fn test_clip_against_zero_to_one_depth() {
+        // z from -0.5 to 0.5 is half outside of the frustum when depth starts at 0
+        let triangle = [vertex(0.0, 0.0, -0.5, 1.0), vertex(0.5, 0.0, 0.5, 1.0), vertex(0.0, 0.5, 0.5, 1.0)];
+        assert_eq!(clip(&triangle, DepthRange::MinusOneToOne).len(), 3);
+        let polygon = clip(&triangle, DepthRange::ZeroToOne);
+        assert_eq!(polygon.len(), 4);
+        for v in polygon.iter()
+        {
+            assert!(inside(&v.position_cc, DepthRange::ZeroToOne));
+            assert!(v.position_cc.z() > -1e-6);
+        }
+    }
+}
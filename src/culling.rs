@@ -0,0 +1,136 @@
+use rayon::prelude::*;
+
+use crate::clipping::DepthRange;
+use crate::data::{Mat4, Vec3, VecDot};
+use crate::shading::Triangle;
+
+/// Which faces are dropped before rasterization
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CullMode
+{
+    None,
+    Back,
+    Front,
+}
+
+/// Winding order of front faces, as seen from the camera
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Winding
+{
+    Cw,
+    Ccw,
+}
+
+/// Number of triangles dropped by each test of `cull`
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CullStats
+{
+    pub faces: usize,
+    pub frustum: usize,
+}
+
+impl CullStats
+{
+    pub fn total(&self) -> usize
+    {
+        self.faces + self.frustum
+    }
+}
+
+/// Keeps the triangles that may cover a pixel
+///
+/// Triangles facing away according to `cull_mode` and `front_face` are dropped, then triangles
+/// whose corners are all outside of the same frustum plane. Degenerate triangles have no facing
+/// and are only dropped when faces are culled. The kept triangles are projected to clip space.
+pub fn cull<'a>(triangles_ec: Vec<Triangle<'a>>, perspective_mat: &Mat4, depth_range: DepthRange,
+                cull_mode: CullMode, front_face: Winding) -> (Vec<Triangle<'a>>, CullStats)
+{
+    let total = triangles_ec.len();
+    let mut facing_culled = 0;
+    let triangles_ec: Vec<Triangle> = match cull_mode {
+        CullMode::None => triangles_ec,
+        CullMode::Back | CullMode::Front => {
+            let keep_front = cull_mode == CullMode::Back;
+            let kept: Vec<Triangle> = triangles_ec.into_par_iter()
+                .filter(|t| {
+                    let facing = facing(t, front_face);
+                    facing != 0.0 && (facing > 0.0) == keep_front
+                })
+                .collect();
+            facing_culled = total - kept.len();
+            kept
+        }
+    };
+    let before_frustum = triangles_ec.len();
+    // the projected corners stay with the triangles for clipping
+    let triangles_ec: Vec<Triangle> = triangles_ec.into_par_iter()
+        .map(|t| t.project(perspective_mat, depth_range))
+        .filter(|t| {
+            let codes = t.outcodes().unwrap();
+            codes[0] & codes[1] & codes[2] == 0
+        })
+        .collect();
+    let stats = CullStats {
+        faces: facing_culled,
+        frustum: before_frustum - triangles_ec.len(),
+    };
+    return (triangles_ec, stats);
+}
+
+/// Positive for front faces and negative for back faces, zero for degenerate triangles
+///
+/// Facing is decided in eye coordinates, where the camera sits at the origin, so triangles
+/// crossing the near plane are handled too.
+fn facing(triangle_ec: &Triangle, front_face: Winding) -> f32
+{
+    let ccw_normal = triangle_ec.ccw_normal();
+    // the camera looks at the ccw side when it is on the side the normal points to
+    let towards_camera = -ccw_normal.dot(&Vec3::from(triangle_ec.positions()[0]));
+    return match front_face {
+        Winding::Ccw => towards_camera,
+        Winding::Cw => -towards_camera,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::Vec4;
+    use crate::shading::Vertex;
+    use crate::transformations::perspective;
+
+    use super::*;
+
+    fn vertices() -> Vec<Vertex>
+    {
+        // a ccw triangle facing the camera, a ccw triangle facing away, and a triangle off to the right
+        let xyz = [
+            (-1.0, 0.0, -2.0), (1.0, 0.0, -2.0), (0.0, 1.0, -2.0),
+            (-1.0, 0.0, -2.0), (0.0, 1.0, -2.0), (1.0, 0.0, -2.0),
+            (10.0, 0.0, -2.0), (12.0, 0.0, -2.0), (11.0, 1.0, -2.0),
+        ];
+        xyz.iter().enumerate().map(|(idx, (x, y, z))| Vertex { position: Vec4::new_xyzw(*x, *y, *z, 1.0), idx }).collect()
+    }
+
+    #[test]
+    fn test_cull_modes() {
+        let vertices = vertices();
+        let triangles = || (0..3).map(|i| Triangle::new(&vertices[3 * i], &vertices[3 * i + 1], &vertices[3 * i + 2])).collect::<Vec<Triangle>>();
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+
+        let (kept, stats) = cull(triangles(), &perspective_mat, DepthRange::MinusOneToOne, CullMode::None, Winding::Ccw);
+        assert_eq!((kept.len(), stats.faces, stats.frustum), (2, 0, 1));
+        assert!(kept.iter().all(|t| t.outcodes() == Some([0, 0, 0])));
+
+        let (kept, stats) = cull(triangles(), &perspective_mat, DepthRange::MinusOneToOne, CullMode::Back, Winding::Ccw);
+        assert_eq!((kept.len(), stats.faces, stats.frustum), (1, 1, 1));
+        assert_eq!(kept[0].positions()[1].y(), 0.0);
+
+        let (kept, stats) = cull(triangles(), &perspective_mat, DepthRange::MinusOneToOne, CullMode::Front, Winding::Ccw);
+        assert_eq!((kept.len(), stats.total()), (1, 2));
+        assert_eq!(kept[0].positions()[1].y(), 1.0);
+
+        // with clockwise front faces, the second triangle is the front one
+        let (kept, _) = cull(triangles(), &perspective_mat, DepthRange::MinusOneToOne, CullMode::Back, Winding::Cw);
+        assert_eq!(kept[0].positions()[1].y(), 1.0);
+    }
+}
@@ -369,8 +369,56 @@ impl ScalarMul for Mat4 {
 }
 
 impl Inverse for Mat4 {
+    // Reference: https://www.geometrictools.com/Documentation/LaplaceExpansionTheorem.pdf
     fn inverse(&self) -> Self {
-        unimplemented!()
+        let m = |row: usize, col: usize| self._get_entry(row, col);
+        let s0 = m(0, 0) * m(1, 1) - m(1, 0) * m(0, 1);
+        let s1 = m(0, 0) * m(1, 2) - m(1, 0) * m(0, 2);
+        let s2 = m(0, 0) * m(1, 3) - m(1, 0) * m(0, 3);
+        let s3 = m(0, 1) * m(1, 2) - m(1, 1) * m(0, 2);
+        let s4 = m(0, 1) * m(1, 3) - m(1, 1) * m(0, 3);
+        let s5 = m(0, 2) * m(1, 3) - m(1, 2) * m(0, 3);
+
+        let c5 = m(2, 2) * m(3, 3) - m(3, 2) * m(2, 3);
+        let c4 = m(2, 1) * m(3, 3) - m(3, 1) * m(2, 3);
+        let c3 = m(2, 1) * m(3, 2) - m(3, 1) * m(2, 2);
+        let c2 = m(2, 0) * m(3, 3) - m(3, 0) * m(2, 3);
+        let c1 = m(2, 0) * m(3, 2) - m(3, 0) * m(2, 2);
+        let c0 = m(2, 0) * m(3, 1) - m(3, 0) * m(2, 1);
+
+        let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        let data = [
+            [
+                m(1, 1) * c5 - m(1, 2) * c4 + m(1, 3) * c3,
+                -m(0, 1) * c5 + m(0, 2) * c4 - m(0, 3) * c3,
+                m(3, 1) * s5 - m(3, 2) * s4 + m(3, 3) * s3,
+                -m(2, 1) * s5 + m(2, 2) * s4 - m(2, 3) * s3,
+            ],
+            [
+                -m(1, 0) * c5 + m(1, 2) * c2 - m(1, 3) * c1,
+                m(0, 0) * c5 - m(0, 2) * c2 + m(0, 3) * c1,
+                -m(3, 0) * s5 + m(3, 2) * s2 - m(3, 3) * s1,
+                m(2, 0) * s5 - m(2, 2) * s2 + m(2, 3) * s1,
+            ],
+            [
+                m(1, 0) * c4 - m(1, 1) * c2 + m(1, 3) * c0,
+                -m(0, 0) * c4 + m(0, 1) * c2 - m(0, 3) * c0,
+                m(3, 0) * s4 - m(3, 1) * s2 + m(3, 3) * s0,
+                -m(2, 0) * s4 + m(2, 1) * s2 - m(2, 3) * s0,
+            ],
+            [
+                -m(1, 0) * c3 + m(1, 1) * c1 - m(1, 2) * c0,
+                m(0, 0) * c3 - m(0, 1) * c1 + m(0, 2) * c0,
+                -m(3, 0) * s3 + m(3, 1) * s1 - m(3, 2) * s0,
+                m(2, 0) * s3 - m(2, 1) * s1 + m(2, 2) * s0,
+            ],
+        ];
+        let mut inverse = Mat4 {
+            transposed: false,
+            data,
+        };
+        inverse.scalar_div_(det);
+        return inverse;
     }
 }
 
@@ -635,6 +683,231 @@ impl Inverse for Mat3 {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
+pub struct Vec2 {
+    transposed: bool,
+    data: [f32; 2],
+}
+
+impl Vec for Vec2 {
+    fn get(&self, index: usize) -> Result<f32, OutOfBoundError> {
+        return if index > 1 {
+            Err(OutOfBoundError::new([1, 0], [index, 0]))
+        } else {
+            Ok(self.data[index])
+        };
+    }
+
+    fn set(&mut self, index: usize, val: f32) -> Result<(), OutOfBoundError> {
+        return if index > 1 {
+            Err(OutOfBoundError::new([1, 0], [index, 0]))
+        } else {
+            self.data[index] = val;
+            Ok(())
+        };
+    }
+
+    fn get_size(&self) -> usize {
+        2
+    }
+}
+
+impl VecDot for Vec2 {
+    fn dot(&self, other: &Self) -> f32 {
+        let accum = self.x() * other.x() + self.y() * other.y();
+        return accum;
+    }
+}
+
+impl Add for Vec2 {
+    fn add(&self, other: &Vec2) -> Result<Self, DimensionMismatchError> {
+        if self.transposed != other.transposed {
+            return Err(DimensionMismatchError::new(
+                if self.transposed { [1, 2] } else { [2, 1] },
+                if other.transposed { [1, 2] } else { [2, 1] },
+            ));
+        } else {
+            let d = [self.x() + other.x(), self.y() + other.y()];
+            return Ok(Vec2 {
+                data: d,
+                transposed: self.transposed,
+            });
+        }
+    }
+
+    fn add_(&mut self, other: &Self) {
+        self.data[0] += other.data[0];
+        self.data[1] += other.data[1];
+    }
+
+    fn _add(&self, v: &Vec2) -> Vec2 {
+        let data = [self.data[0] + v.data[0], self.data[1] + v.data[1]];
+        return Vec2 {
+            transposed: false,
+            data,
+        };
+    }
+}
+
+impl Minus for Vec2 {
+    fn minus(&self, right: &Self) -> Result<Self, DimensionMismatchError> {
+        if self.transposed != right.transposed {
+            return Err(DimensionMismatchError::new(
+                if self.transposed { [1, 2] } else { [2, 1] },
+                if right.transposed { [1, 2] } else { [2, 1] },
+            ));
+        } else {
+            let d = [self.x() - right.x(), self.y() - right.y()];
+            return Ok(Vec2 {
+                data: d,
+                transposed: self.transposed,
+            });
+        }
+    }
+
+    fn minus_(&mut self, right: &Self) {
+        self.data[0] -= right.data[0];
+        self.data[1] -= right.data[1];
+    }
+
+    fn _minus(&self, right: &Self) -> Self {
+        let data = [self.data[0] - right.data[0], self.data[1] - right.data[1]];
+        return Vec2 {
+            transposed: false,
+            data,
+        };
+    }
+}
+
+impl Transpose for Vec2 {
+    fn transpose(&self) -> Self {
+        let mut v = self.clone();
+        v.transposed = !self.transposed;
+        return v;
+    }
+
+    fn transpose_(&mut self) {
+        self.transposed = !self.transposed;
+    }
+}
+
+impl Length for Vec2 {
+    fn get_length(&self) -> f32 {
+        let x2 = self.data[0] * self.data[0];
+        let y2 = self.data[1] * self.data[1];
+        let l2 = x2 + y2;
+        return l2.sqrt();
+    }
+}
+
+impl Product for Vec2 {
+    fn product(&self, rhs: &Self) -> Self {
+        let v = Vec2::new_xy(self.x() * rhs.x(), self.y() * rhs.y());
+        return v;
+    }
+
+    fn product_(&mut self, rhs: &Self) {
+        self.data[0] *= rhs.data[0];
+        self.data[1] *= rhs.data[1];
+    }
+}
+
+impl Normalize for Vec2 {
+    fn normalize(&self) -> Self {
+        let l = self.get_length();
+        Vec2::new_xy(self.data[0] / l, self.data[1] / l)
+    }
+
+    fn normalize_(&mut self) {
+        let l = self.get_length();
+        self.data[0] /= l;
+        self.data[1] /= l;
+    }
+}
+
+impl ScalarDiv for Vec2 {
+    fn scalar_div(&self, s: f32) -> Self {
+        let vec = Vec2::new_xy(self.x() / s, self.y() / s);
+        return vec;
+    }
+
+    fn scalar_div_(&mut self, s: f32) {
+        self.data[0] /= s;
+        self.data[1] /= s;
+    }
+}
+
+impl ScalarMul for Vec2 {
+    fn scalar_mul(&self, s: f32) -> Self {
+        let vec = Vec2::new_xy(self.x() * s, self.y() * s);
+        return vec;
+    }
+
+    fn scalar_mul_(&mut self, s: f32) {
+        self.data[0] *= s;
+        self.data[1] *= s;
+    }
+}
+
+impl Vec2 {
+    pub fn new(val: f32) -> Self {
+        Vec2 {
+            transposed: false,
+            data: [val, val],
+        }
+    }
+
+    pub fn new_xy(x: f32, y: f32) -> Self {
+        Vec2 {
+            transposed: false,
+            data: [x, y],
+        }
+    }
+
+    pub fn new_uv(u: f32, v: f32) -> Self {
+        Vec2 {
+            transposed: false,
+            data: [u, v],
+        }
+    }
+
+    #[inline]
+    pub fn x(&self) -> f32 {
+        self.data[0]
+    }
+    #[inline]
+    pub fn y(&self) -> f32 {
+        self.data[1]
+    }
+
+    #[inline]
+    pub fn u(&self) -> f32 {
+        self.data[0]
+    }
+    #[inline]
+    pub fn v(&self) -> f32 {
+        self.data[1]
+    }
+
+    #[inline]
+    pub fn set_x(&mut self, x: f32) {
+        self.data[0] = x;
+    }
+    #[inline]
+    pub fn set_y(&mut self, y: f32) {
+        self.data[1] = y;
+    }
+
+    #[inline]
+    pub fn set_u(&mut self, u: f32) {
+        self.data[0] = u;
+    }
+    #[inline]
+    pub fn set_v(&mut self, v: f32) {
+        self.data[1] = v;
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Vec3 {
     transposed: bool,
@@ -1257,4 +1530,21 @@ mod test {
         let cross = v2.cross(&v3);
         assert_eq!(cross.x(), 1.);
     }
+
+    #[test]
+    fn test_mat4_inverse() {
+        let mut m = Mat4::identity();
+        m._set_row(0, &Vec4::new_xyzw(2.0, 0.0, 0.0, 1.0));
+        m._set_row(1, &Vec4::new_xyzw(0.0, 0.0, -3.0, 2.0));
+        m._set_row(2, &Vec4::new_xyzw(0.0, 4.0, 0.0, 3.0));
+        let prod = m.dot_mat(&m.inverse());
+        for row in 0..4 {
+            for col in 0..4 {
+                let expected = if row == col { 1.0 } else { 0.0 };
+                assert!((prod._get_entry(row, col) - expected).abs() < 1e-6);
+            }
+        }
+        let prod = m.transpose().dot_mat(&m.transpose().inverse());
+        assert!((prod._get_entry(2, 2) - 1.0).abs() < 1e-6);
+    }
 }
@@ -47,4 +47,29 @@ impl fmt::Display for OutOfBoundError
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "Max x = {}, Max y = {}, got ({}, {})", self.range[0], self.range[1], self.got[0], self.got[1])
     }
-}
\ No newline at end of file
+}
+#[derive(Copy, Clone, Debug)]
+pub struct VaryingsFullError
+{
+    max_varyings: usize,
+    max_floats: usize
+}
+
+impl VaryingsFullError
+{
+    pub fn new(max_varyings: usize, max_floats: usize) -> Self
+    {
+        VaryingsFullError
+        {
+            max_varyings,
+            max_floats
+        }
+    }
+}
+
+impl fmt::Display for VaryingsFullError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "A vertex has at most {} varyings of {} floats in total", self.max_varyings, self.max_floats)
+    }
+}
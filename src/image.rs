@@ -0,0 +1,341 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::buffer::FrameBuffer;
+use crate::data::Vec3;
+
+/// Writes the frame to `path`, picking the format from the file extension (`png`, `ppm` or `pfm`)
+pub fn save(frame: &FrameBuffer, path: &Path) -> io::Result<()>
+{
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let writer = || File::create(path).map(BufWriter::new);
+    return match extension.as_deref() {
+        Some("png") => write_png(frame, writer()?),
+        Some("ppm") => write_ppm(frame, writer()?),
+        Some("pfm") => write_pfm(frame, writer()?),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                format!("cannot infer image format of {}, expected .png, .ppm or .pfm", path.display())))
+    };
+}
+
+/// Encodes the frame as an 8-bit RGBA PNG
+pub fn write_png<W: Write>(frame: &FrameBuffer, writer: W) -> io::Result<()>
+{
+    let mut encoder = png::Encoder::new(writer, frame.width() as u32, frame.height() as u32);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(frame.as_bytes())?;
+    return Ok(());
+}
+
+/// Encodes the frame as a binary (P6) PPM, dropping the alpha channel
+pub fn write_ppm<W: Write>(frame: &FrameBuffer, mut writer: W) -> io::Result<()>
+{
+    write!(writer, "P6\n{} {}\n255\n", frame.width(), frame.height())?;
+    let rgb: Vec<u8> = frame.as_bytes().chunks_exact(4).flat_map(|p| p[..3].iter().cloned()).collect();
+    writer.write_all(&rgb)?;
+    return writer.flush();
+}
+
+/// Encodes the unclamped float colors of the frame as a little-endian color PFM
+///
+/// PFM stores rows from bottom to top.
+pub fn write_pfm<W: Write>(frame: &FrameBuffer, mut writer: W) -> io::Result<()>
+{
+    write!(writer, "PF\n{} {}\n-1.0\n", frame.width(), frame.height())?;
+    let mut data = Vec::with_capacity(frame.width() * frame.height() * 12);
+    for row in frame.as_f32().chunks_exact(frame.width()).rev()
+    {
+        for color in row.iter()
+        {
+            data.extend_from_slice(&color.r().to_le_bytes());
+            data.extend_from_slice(&color.g().to_le_bytes());
+            data.extend_from_slice(&color.b().to_le_bytes());
+        }
+    }
+    writer.write_all(&data)?;
+    return writer.flush();
+}
+
+/// Writes a grayscale frame such as `Renderer::depth_frame` to `path`, as a `png` or a `pfm` by extension
+///
+/// Only the red channel is kept, so both formats store a single channel.
+pub fn save_depth(frame: &FrameBuffer, path: &Path) -> io::Result<()>
+{
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let writer = || File::create(path).map(BufWriter::new);
+    return match extension.as_deref() {
+        Some("png") => write_gray_png(frame, writer()?),
+        Some("pfm") => write_gray_pfm(frame, writer()?),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                format!("cannot infer depth image format of {}, expected .png or .pfm", path.display())))
+    };
+}
+
+/// Encodes the red channel of the frame as an 8-bit grayscale PNG
+pub fn write_gray_png<W: Write>(frame: &FrameBuffer, writer: W) -> io::Result<()>
+{
+    let mut encoder = png::Encoder::new(writer, frame.width() as u32, frame.height() as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    let gray: Vec<u8> = frame.as_bytes().chunks_exact(4).map(|p| p[0]).collect();
+    writer.write_image_data(&gray)?;
+    return Ok(());
+}
+
+/// Encodes the unclamped red channel of the frame as a little-endian grayscale (Pf) PFM
+pub fn write_gray_pfm<W: Write>(frame: &FrameBuffer, mut writer: W) -> io::Result<()>
+{
+    write!(writer, "Pf\n{} {}\n-1.0\n", frame.width(), frame.height())?;
+    let mut data = Vec::with_capacity(frame.width() * frame.height() * 4);
+    for row in frame.as_f32().chunks_exact(frame.width()).rev()
+    {
+        for color in row.iter()
+        {
+            data.extend_from_slice(&color.r().to_le_bytes());
+        }
+    }
+    writer.write_all(&data)?;
+    return writer.flush();
+}
+
+/// Reads a PNG or PPM image into a frame, picking the format from the file extension
+pub fn load(path: &Path) -> io::Result<FrameBuffer>
+{
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let reader = || File::open(path).map(BufReader::new);
+    return match extension.as_deref() {
+        Some("png") => read_png(reader()?),
+        Some("ppm") => read_ppm(reader()?),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                format!("cannot infer image format of {}, expected .png or .ppm", path.display())))
+    };
+}
+
+/// Most pixels of an image that is read, its header is not trusted with larger allocations
+const MAX_IMAGE_PIXELS: usize = 1 << 26;
+
+/// Decodes a PNG of any color type and bit depth, the alpha channel is dropped
+pub fn read_png<R: Read>(reader: R) -> io::Result<FrameBuffer>
+{
+    let mut decoder = png::Decoder::new(reader);
+    // palettes and low bit depths are expanded to 8 bits, 16 bits are stripped down to 8 bits
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.width as usize * info.height as usize > MAX_IMAGE_PIXELS
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("invalid PNG: {}x{} is larger than {} pixels", info.width, info.height, MAX_IMAGE_PIXELS)));
+    }
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => unreachable!("palettes are expanded to RGB"),
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+    let rows = data.chunks_exact(info.line_size).map(|row| row[..width * channels].chunks_exact(channels).map(|p| {
+        match channels {
+            1 | 2 => [p[0]; 3],
+            _ => [p[0], p[1], p[2]],
+        }
+    }));
+    let mut frame = FrameBuffer::new(width, height);
+    for (row, pixels) in rows.enumerate()
+    {
+        for (x, rgb) in pixels.enumerate()
+        {
+            frame.set(x, height - 1 - row, to_vec3(rgb, 255));
+        }
+    }
+    return Ok(frame);
+}
+
+/// Decodes a binary (P6) or ASCII (P3) PPM with at most 8 bits per channel
+pub fn read_ppm<R: Read>(reader: R) -> io::Result<FrameBuffer>
+{
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid PPM: {}", msg));
+    let mut reader = BufReader::new(reader);
+    let magic = read_ppm_token(&mut reader)?;
+    let binary = match magic.as_str() {
+        "P6" => true,
+        "P3" => false,
+        _ => return Err(invalid("expected P6 or P3 magic number")),
+    };
+    let mut header = [0usize; 3];
+    for value in header.iter_mut()
+    {
+        *value = read_ppm_token(&mut reader)?.parse().map_err(|_| invalid("bad header"))?;
+    }
+    let [width, height, max_value] = header;
+    if width == 0 || height == 0 || max_value == 0 || max_value > 255
+    {
+        return Err(invalid("only non-empty images with up to 8 bits per channel are supported"));
+    }
+    let samples = match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_IMAGE_PIXELS => pixels * 3,
+        _ => return Err(invalid(&format!("{}x{} is larger than {} pixels", width, height, MAX_IMAGE_PIXELS))),
+    };
+    let mut data = vec![0; samples];
+    if binary
+    {
+        // exactly one whitespace separates the header from the samples, read_ppm_token consumed it
+        reader.read_exact(&mut data)?;
+    } else {
+        for sample in data.iter_mut()
+        {
+            *sample = read_ppm_token(&mut reader)?.parse().map_err(|_| invalid("bad sample"))?;
+        }
+    }
+    let mut frame = FrameBuffer::new(width, height);
+    for (i, rgb) in data.chunks_exact(3).enumerate()
+    {
+        frame.set(i % width, height - 1 - i / width, to_vec3([rgb[0], rgb[1], rgb[2]], max_value as u8));
+    }
+    return Ok(frame);
+}
+
+/// Reads the next whitespace separated token of a PPM header, skipping `#` comments
+///
+/// The single whitespace after the token is consumed as well.
+fn read_ppm_token<R: BufRead>(reader: &mut R) -> io::Result<String>
+{
+    let mut token = String::new();
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0
+        {
+            return if token.is_empty() { Err(io::ErrorKind::UnexpectedEof.into()) } else { Ok(token) };
+        }
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                let mut comment = Vec::new();
+                reader.read_until(b'\n', &mut comment)?;
+            }
+            c if c.is_ascii_whitespace() => {
+                if !token.is_empty()
+                {
+                    return Ok(token);
+                }
+            }
+            c => token.push(c as char),
+        }
+    }
+}
+
+#[inline]
+fn to_vec3(rgb: [u8; 3], max_value: u8) -> Vec3
+{
+    let max_value = max_value as f32;
+    Vec3::new_rgb(rgb[0] as f32 / max_value, rgb[1] as f32 / max_value, rgb[2] as f32 / max_value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame() -> FrameBuffer
+    {
+        let mut frame = FrameBuffer::new(2, 1);
+        frame.set(0, 0, Vec3::new_rgb(1.0, 0.5, 0.0));
+        frame.set(1, 0, Vec3::new_rgb(2.0, -1.0, 0.25));
+        return frame;
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut bytes = Vec::new();
+        write_ppm(&frame(), &mut bytes).unwrap();
+        let header = b"P6\n2 1\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], &[255, 128, 0, 255, 0, 64]);
+    }
+
+    #[test]
+    fn test_write_pfm_keeps_unclamped_colors() {
+        let mut bytes = Vec::new();
+        write_pfm(&frame(), &mut bytes).unwrap();
+        let header = b"PF\n2 1\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let floats: Vec<f32> = bytes[header.len()..].chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(floats, vec![1.0, 0.5, 0.0, 2.0, -1.0, 0.25]);
+    }
+
+    #[test]
+    fn test_write_gray_images() {
+        let mut bytes = Vec::new();
+        write_gray_pfm(&frame(), &mut bytes).unwrap();
+        let header = b"Pf\n2 1\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 8);
+        assert_eq!(f32::from_le_bytes([bytes[header.len() + 4], bytes[header.len() + 5], bytes[header.len() + 6], bytes[header.len() + 7]]), 2.0);
+        let mut png = Vec::new();
+        write_gray_png(&frame(), &mut png).unwrap();
+        let decoded = read_png(&png[..]).unwrap();
+        assert_eq!(decoded.get(0, 0), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_write_png_signature() {
+        let mut bytes = Vec::new();
+        write_png(&frame(), &mut bytes).unwrap();
+        assert_eq!(&bytes[..8], &[137, 80, 78, 71, 13, 10, 26, 10]);
+    }
+
+    #[test]
+    fn test_read_back_written_images() {
+        let mut ppm = Vec::new();
+        write_ppm(&frame(), &mut ppm).unwrap();
+        let mut png = Vec::new();
+        write_png(&frame(), &mut png).unwrap();
+        for decoded in [read_ppm(&ppm[..]).unwrap(), read_png(&png[..]).unwrap()].iter()
+        {
+            assert_eq!((decoded.width(), decoded.height()), (2, 1));
+            assert_eq!(decoded.get(0, 0), [255, 128, 0, 255]);
+            assert_eq!(decoded.get(1, 0), [255, 0, 64, 255]);
+        }
+    }
+
+    #[test]
+    fn test_read_huge_png() {
+        // a header, with an empty chunk of data that lets the decoder read it
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 100000, 100000);
+            encoder.set_color(png::ColorType::RGBA);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_chunk(*b"IDAT", &[]).unwrap();
+        }
+        assert_eq!(read_png(&png[..]).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_ascii_ppm() {
+        let ppm = b"P3\n# a comment\n1 2\n15\n15 0 0\n0 15 0\n";
+        let decoded = read_ppm(&ppm[..]).unwrap();
+        // the first row in the file is the top one
+        assert_eq!(decoded.get(0, 1), [255, 0, 0, 255]);
+        assert_eq!(decoded.get(0, 0), [0, 255, 0, 255]);
+        assert!(read_ppm(&b"P5\n1 1\n255\n0"[..]).is_err());
+        assert!(read_ppm(&b"P6\n1 1\n255\n0"[..]).is_err());
+        // sizes that overflow or would need a huge allocation
+        let huge = format!("P6\n{} {}\n255\n", usize::MAX, 2);
+        assert_eq!(read_ppm(huge.as_bytes()).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert!(read_ppm(&b"P6\n100000 100000\n255\n0"[..]).is_err());
+    }
+}
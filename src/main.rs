@@ -1,208 +1,267 @@
 use std::collections::HashMap;
 use std::ops::IndexMut;
-use std::time::Instant;
+use std::path::Path;
+use std::sync::Arc;
 
 use pixel_canvas::{Canvas, Color, XY};
 use pixel_canvas::input::glutin::event::VirtualKeyCode;
-use rayon::prelude::*;
-use tobj::Mesh;
 
-use crate::data::{Add, Cross, Mat4, MatVecDot, Minus, Normalize, ScalarDiv, ScalarMul, Transpose, Vec3, Vec4, VecDot};
+use crate::antialiasing::Downsample;
+use crate::bounds::BoundingSphere;
+use crate::buffer::FrameBuffer;
+use crate::cli::{Command, Options};
+use crate::culling::CullMode;
+use crate::data::{Add, Cross, Mat4, MatVecDot, Minus, Normalize, ScalarDiv, ScalarMul, Vec3, Vec4, VecDot};
+use crate::mesh::get_position_os;
+use crate::renderer::Renderer;
+use crate::scene::Scene;
+use crate::shader::ShadingMode;
 use crate::shading::*;
 use crate::state::KeyboardMouseStates;
-use crate::transformations::{perspective, rotate_obj};
+use crate::texture::Texture;
+use crate::transformations::rotate_obj;
 
 mod err;
 mod data;
 mod state;
 mod transformations;
 mod shading;
+mod mesh;
+mod buffer;
+mod renderer;
+mod image;
+mod cli;
+mod bounds;
+mod scene;
+mod clipping;
+mod culling;
+mod tiling;
+mod simd;
+mod antialiasing;
+mod shader;
+mod texture;
+
+const GLOBAL_REFLECTION: f32 = 0.5;
+const SCREENSHOT_PNG_PATH: &'static str = "screenshot.png";
+const SCREENSHOT_PFM_PATH: &'static str = "screenshot.pfm";
+const DEPTH_SCREENSHOT_PNG_PATH: &'static str = "screenshot_depth.png";
+const DEPTH_SCREENSHOT_PFM_PATH: &'static str = "screenshot_depth.pfm";
 
-const OBJ_PATH: &'static str = "data/KAUST_Beacon.obj";
-const OBJECT_CENTER: (f32, f32, f32) = (125.0, 125.0, 125.0);
-const OBJ_BOUNDING_RADIUS: f32 = 125.0;
-const FOV_Y: f32 = std::f32::consts::FRAC_PI_4 * 2.5;
-const NEAR: f32 = 0.01;
-const FAR: f32 = 3.0 * OBJ_BOUNDING_RADIUS;
-const CAMERA_Z_WC: f32 = 1.5 * OBJ_BOUNDING_RADIUS;
-
-pub fn get_position_os(mesh: &Mesh) -> Vec<Vertex>
-{
-    let idxs: Vec<usize> = (0..mesh.positions.len()).step_by(3).collect();
-    let mut positions_os: Vec<Vertex> = idxs.par_iter().map(|i| {
-        let i = *i;
-        let vertex_idx = i / 3;
-        unsafe
-            {
-                let x = *mesh.positions.get_unchecked(i);
-                let y = *mesh.positions.get_unchecked(i + 1);
-                let z = *mesh.positions.get_unchecked(i + 2);
-                return Vertex {
-                    position: Vec4::new_xyzw(x, y, z, 1.0),
-                    idx: vertex_idx,
-                };
-            }
-    }).collect();
-    positions_os.sort_by(|a, b| a.idx.partial_cmp(&b.idx).unwrap());
-    return positions_os;
+fn main() {
+    let command = cli::parse_from(std::env::args_os()).unwrap_or_else(|e| e.exit());
+    match command {
+        Command::View(options) => view(&options),
+        Command::Render { options, output, depth_output } => render(&options, &output, depth_output.as_deref()),
+    }
 }
 
-pub fn get_adj_vertices(mesh: &Mesh) -> HashMap<usize, Vec<(usize, usize)>>
+/// Loads every model of the OBJ file and moves the center of their bounding box to the world origin
+///
+/// Models use the MTL material they are assigned to, or `default_material` if they have none.
+/// Returns the bounding sphere of the scene in world coordinates as well.
+/// Exits the process with a readable message if the file cannot be loaded.
+fn load_scene(options: &Options, default_material: &Material) -> (Scene, BoundingSphere)
 {
-    let mut map = HashMap::<usize, Vec<(usize, usize)>>::new();
-    for i in (0..mesh.indices.len()).step_by(3)
+    let (models, mtl_materials) = match tobj::load_obj(&options.model_path, true) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("error: failed to load {}: {}", options.model_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    println!("model num = {}, material num = {}", models.len(), mtl_materials.len());
+    for model in models.iter()
     {
-        unsafe {
-            let idx1 = (*mesh.indices.get_unchecked(i)) as usize;
-            let idx2 = (*mesh.indices.get_unchecked(i + 1)) as usize;
-            let idx3 = (*mesh.indices.get_unchecked(i + 2)) as usize;
-            match map.get_mut(&idx1)
-            {
-                None => {
-                    let v = vec![(idx2, idx3)];
-                    map.insert(idx1, v);
-                }
-                Some(vec) => {
-                    vec.push((idx2, idx3));
-                }
-            }
+        let mesh = &model.mesh;
+        println!("  {}: normal num = {}, texcoord num = {}, triangle num = {}, vertex num = {}",
+                 model.name, mesh.normals.len() / 3, mesh.texcoords.len() / 2, mesh.indices.len() / 3,
+                 mesh.positions.len() / 3);
+    }
 
-            match map.get_mut(&idx2)
-            {
-                None => {
-                    let v = vec![(idx3, idx1)];
-                    map.insert(idx2, v);
-                }
-                Some(vec) => {
-                    vec.push((idx3, idx1));
-                }
-            }
+    let vertices_os: Vec<Vertex> = models.iter().flat_map(|m| get_position_os(&m.mesh.positions)).collect();
+    let sphere_os = match BoundingSphere::from_vertices(&vertices_os) {
+        Some(sphere) => sphere,
+        None => {
+            eprintln!("error: {} contains no vertex", options.model_path.display());
+            std::process::exit(1);
+        }
+    };
+    let object_center = options.object_center.unwrap_or(sphere_os.center);
+    let sphere_wc = BoundingSphere {
+        center: sphere_os.center._minus(&object_center),
+        radius: options.bounding_radius.unwrap_or(sphere_os.radius),
+    };
+    println!("bounding sphere center = {:?}, radius = {}", sphere_os.center, sphere_os.radius);
+
+    let obj_translation = object_center.scalar_mul(-1.0);
+    let obj_os_to_wc_transformation = transformations::translate_obj(&Mat4::identity(), &obj_translation);
+    let materials: Vec<Material> = mtl_materials.iter().map(|m| Material::from_mtl(m, default_material)).collect();
+    let mut scene = Scene::from_models(models, &materials, &obj_os_to_wc_transformation, default_material,
+                                       options.normal_mode);
+    load_textures(&mut scene, options);
+    return (scene, sphere_wc);
+}
 
-            match map.get_mut(&idx3)
-            {
-                None => {
-                    let v = vec![(idx1, idx2)];
-                    map.insert(idx3, v);
+/// Loads the diffuse texture of every object, relative to the directory of the OBJ file
+///
+/// Objects whose texture cannot be loaded are rendered without it.
+fn load_textures(scene: &mut Scene, options: &Options)
+{
+    let base_dir = options.model_path.parent().unwrap_or(Path::new(""));
+    // materials shared by several objects load their texture only once
+    let mut loaded: HashMap<String, Option<Arc<Texture>>> = HashMap::new();
+    for object in scene.objects.iter_mut()
+    {
+        let name = match &object.material.diffuse_texture {
+            Some(name) => name,
+            None => continue
+        };
+        let texture = loaded.entry(name.clone()).or_insert_with(|| {
+            let path = base_dir.join(name);
+            match Texture::load(&path) {
+                Ok(mut texture) => {
+                    println!("texture {}: {} x {}, {} mip levels", path.display(), texture.width(), texture.height(),
+                             texture.level_num());
+                    texture.filter = options.texture_filter;
+                    texture.wrap = options.texture_wrap;
+                    texture.max_anisotropy = options.max_anisotropy;
+                    Some(Arc::new(texture))
                 }
-                Some(vec) => {
-                    vec.push((idx1, idx2));
+                Err(e) => {
+                    eprintln!("warning: failed to load texture {}: {}", path.display(), e);
+                    None
                 }
             }
-        }
+        });
+        object.diffuse_texture = texture.clone();
     }
-    return map;
 }
 
-pub fn get_triangles<'a>(vertices: &'a Vec<Vertex>, normals: &'a Vec<Normal>, mesh: &Mesh) -> Vec<Triangle<'a>>
+/// Material of meshes that have no MTL material
+fn material(options: &Options) -> Material
 {
-    let idxs: Vec<usize> = (0..mesh.indices.len()).step_by(3).collect();
-    let triangles: Vec<Triangle> = idxs.par_iter().map(|i| {
-        let i = *i;
-        unsafe {
-            let idx1 = (*mesh.indices.get_unchecked(i)) as usize;
-            let idx2 = (*mesh.indices.get_unchecked(i + 1)) as usize;
-            let idx3 = (*mesh.indices.get_unchecked(i + 2)) as usize;
-            let triangle = Triangle::new((vertices.get_unchecked(idx1), normals.get_unchecked(idx1)),
-                                         (vertices.get_unchecked(idx2), normals.get_unchecked(idx2)),
-                                         (vertices.get_unchecked(idx3), normals.get_unchecked(idx3)));
-            return triangle;
-        }
-    }).collect();
-    return triangles;
+    let material = Material::new(options.ambient, options.diffuse, options.specular, Vec3::new(GLOBAL_REFLECTION),
+                                 options.shininess);
+    if options.metallic.is_none() && options.roughness.is_none()
+    {
+        return material;
+    }
+    let approximated = material.metallic_roughness();
+    return material.with_metallic_roughness(options.metallic.unwrap_or(approximated.metallic),
+                                            options.roughness.unwrap_or(approximated.roughness));
 }
 
-pub fn get_normals(vertices: &Vec<Vertex>, adj_vertices_map: &HashMap<usize, Vec<(usize, usize)>>) -> Vec<Normal>
+fn default_eye(options: &Options, sphere_wc: &BoundingSphere) -> Vec3
 {
-    let mut normals: Vec<Normal> = adj_vertices_map.par_iter().map(|(vertex, adj_point_vertices)| {
-        unsafe {
-            let mut v_p = vertices.get_unchecked(*vertex).position.clone();
-            v_p.scalar_div_(v_p.w());
-            let v_p = Vec3::from(&v_p);
-            let mut vn = Vec3::new(0.0);
-            for adj_vertices in adj_point_vertices.iter()
-            {
-                let mut v1_p = vertices.get_unchecked(adj_vertices.0).position.clone();
-                let mut v2_p = vertices.get_unchecked(adj_vertices.1).position.clone();
-                v1_p.scalar_div_(v1_p.w());
-                v2_p.scalar_div_(v2_p.w());
+    let aspect = (options.width as f32) / (options.height as f32);
+    let distance = sphere_wc.fit_distance(options.fov_y_deg.to_radians(), aspect);
+    options.eye.unwrap_or(options.target._add(&Vec3::new_xyz(0.0, 0.0, distance)))
+}
 
-                let v1_p = Vec3::from(&v1_p);
-                let v2_p = Vec3::from(&v2_p);
-                let v_v1 = v1_p._minus(&v_p);
-                let v_v2 = v2_p._minus(&v_p);
+fn build_renderer(options: &Options, sphere_wc: &BoundingSphere) -> Renderer
+{
+    let eye = default_eye(options, sphere_wc);
+    let camera = Camera::new(eye, options.target, options.up);
+    let (near, far) = sphere_wc.clip_planes(&eye, &options.target);
+    let near = options.near.unwrap_or(near);
+    let far = options.far.unwrap_or(far);
+    if !options.infinite_far && near >= far
+    {
+        eprintln!("error: the near plane at {} must be closer than the far plane at {}", near, far);
+        std::process::exit(1);
+    }
+    let mut renderer = Renderer::new(options.width, options.height, options.fov_y_deg.to_radians(), near, far,
+                                     camera, options.lights.clone());
+    renderer.shader = options.shading.shader();
+    renderer.lighting = options.lighting;
+    renderer.cull_mode = options.cull_mode;
+    renderer.front_face = options.front_face;
+    renderer.depth_func = options.depth_func;
+    renderer.depth_write = options.depth_write;
+    renderer.reversed_z = options.reversed_z;
+    renderer.infinite_far = options.infinite_far;
+    renderer.depth_far = options.depth_far;
+    renderer.msaa = options.msaa;
+    renderer.ssaa_factor = options.ssaa_factor;
+    renderer.ssaa_filter = options.ssaa_filter;
+    renderer.fxaa = options.fxaa;
+    return renderer;
+}
 
-                let mut n = v_v1.cross(&v_v2);
-                n.normalize_();
-                vn.add_(&n);
-            }
-            vn.normalize_();
-            return Normal {
-                vertex_idx: *vertex,
-                vec: Vec4::from(&vn, 0.0),
-            };
+fn render(options: &Options, output: &Path, depth_output: Option<&Path>)
+{
+    let (scene, sphere_wc) = load_scene(options, &material(options));
+    let mut renderer = build_renderer(options, &sphere_wc);
+    let mut frame = FrameBuffer::new(options.width, options.height);
+    let stats = renderer.render(&scene, &mut frame);
+    println!("Geometry Time {} ms", stats.geometry_ms);
+    println!("Rasterization Time {} ms", stats.rasterization_ms);
+    println!("Shading Time {} ms", stats.shading_ms);
+    println!("Post-processing Time {} ms", stats.post_processing_ms);
+    println!("Culled {} of {} triangles", stats.culled_triangles, stats.triangles);
+    println!("Early-Z rejected {} samples, hierarchical Z skipped up to {} samples",
+             stats.depth.early_z_rejected, stats.depth.hiz_rejected);
+    if let Err(e) = image::save(&frame, output)
+    {
+        eprintln!("error: failed to save {}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+    println!("Saved {}", output.display());
+    if let Some(depth_output) = depth_output
+    {
+        if let Err(e) = image::save_depth(&renderer.depth_frame(options.depth_remap), depth_output)
+        {
+            eprintln!("error: failed to save {}: {}", depth_output.display(), e);
+            std::process::exit(1);
         }
-    }).collect();
-    normals.sort_by(|a, b| a.vertex_idx.partial_cmp(&b.vertex_idx).unwrap());
-    return normals;
+        println!("Saved {}", depth_output.display());
+    }
 }
 
-const WIDTH: usize = 600;
-const HEIGHT: usize = 600;
-
-fn main() {
-    let (mut models, _) = tobj::load_obj(OBJ_PATH, true).expect("Loading Error");
-    let model = models.pop().unwrap();
-    let mesh = model.mesh;
-    println!("model num = {}", models.len());
-    println!("normal num = {}", mesh.normals.len());
-    println!("triangle num = {}", mesh.num_face_indices.len());
-    println!("indices len = {}", mesh.indices.len());
-    println!("vertex num = {}", mesh.positions.len() / 3);
-
-    let vertices_os = get_position_os(&mesh);
-    let adj_vertices_map = get_adj_vertices(&mesh);
+fn view(options: &Options)
+{
+    let (scene, sphere_wc) = load_scene(options, &material(options));
+    let mut width = options.width;
+    let mut height = options.height;
+    let target = options.target;
+    let up = options.up;
     let identity = Mat4::identity();
-    let obj_translation = Vec3::new_xyz(-OBJECT_CENTER.0, -OBJECT_CENTER.1, -OBJECT_CENTER.2);
-    let obj_os_to_wc_transformation = transformations::translate_obj(&identity, &obj_translation);
-    let vertices_wc: Vec<Vertex> = vertices_os.par_iter().map(|v_os| Vertex {
-        position: obj_os_to_wc_transformation.mat_vec_dot(&v_os.position),
-        idx: v_os.idx,
-    }).collect();
-    let normals_wc: Vec<Normal> = get_normals(&vertices_wc, &adj_vertices_map);
-
-    let light_pos_wc = Vec4::new_xyzw(200.0, 200.0, 200.0, 1.0);
-    let silver_material = Material {
-        ambient: Vec3::new_rgb(0.1, 0.1, 0.2),
-        diffuse: Vec3::new_rgb(0.5, 0.5, 0.6),
-        reflection: Vec3::new_rgb(1.0, 1.0, 1.0),
-        global_reflection: Vec3::new_rgb(0.5, 0.5, 0.5),
-        specular: 16.0,
-    };
+    let mut cam_pos_wc = default_eye(options, &sphere_wc);
+    let mut renderer = build_renderer(options, &sphere_wc);
+    let mut frame = FrameBuffer::new(width, height);
 
-    let mut zbuff = ZBuffer::new(WIDTH, HEIGHT, f32::MAX);
-    let mut gouraud_shading = true;
-
-    let canvas = Canvas::new(WIDTH, HEIGHT)
+    let canvas = Canvas::new(width, height)
         .title("Rusterizer")
         .state(KeyboardMouseStates::new())
         .input(KeyboardMouseStates::handle_input);
 
-    let now = Instant::now();
     let os_windows = cfg!(windows);
-    let mut cam_pos_wc = Vec3::new_xyz(0.0, 0.0, CAMERA_Z_WC);
     let mut arc_ball_initialized = false;
     let mut arc_ball_previous = Vec3::new_xyz(0.0, 0.0, 0.0);
 
+    let mut geometry_time_ema = 0.;
     let mut raster_time_ema = 0.;
     let mut shading_time_ema = 0.;
+    let mut post_processing_time_ema = 0.;
     let ema_alpha = 0.95;
     let ema_beta = 1. - ema_alpha;
 
+    let mut save_screenshot = false;
+    let mut depth_view = false;
+    let mut shading = options.shading;
+    let depth_remap = options.depth_remap;
+
     let every_n_frames = 10;
     let mut i = 0;
 
     canvas.render(move |state, frame_buffer_image| {
-        frame_buffer_image.par_iter_mut().for_each(|e| *e = Color::BLACK);
+        if (frame_buffer_image.width(), frame_buffer_image.height()) != (width, height)
+        {
+            width = frame_buffer_image.width();
+            height = frame_buffer_image.height();
+            renderer.resize(width, height);
+            frame = FrameBuffer::new(width, height);
+        }
         if state.received_mouse_press
         {
             let x = state.x;
@@ -211,13 +270,13 @@ fn main() {
             // y -100, 400
             // x 0, 500
             let mut normalized_x = match os_windows {
-                true => ((x as f32) * 0.8) / (WIDTH as f32),
-                false => (x as f32) / (WIDTH as f32)
+                true => ((x as f32) * 0.8) / (width as f32),
+                false => (x as f32) / (width as f32)
             };
             normalized_x = (normalized_x - 0.5) * 2.0;
             let mut normalized_y = match os_windows {
-                true => ((y as f32) + 100.0) * 0.8 / (HEIGHT as f32),
-                false => (y as f32) / (HEIGHT as f32)
+                true => ((y as f32) + 100.0) * 0.8 / (height as f32),
+                false => (y as f32) / (height as f32)
             };
             normalized_y = (normalized_y - 0.5) * 2.0;
             let sqr = normalized_x * normalized_x - normalized_y * normalized_y;
@@ -236,11 +295,10 @@ fn main() {
                 let sin = v.dot(&arc_ball_previous);
                 let rotate_angle_rad = sin.asin();
                 let rotate_mat = rotate_obj(&identity, rotate_angle_rad, &rotate_axis);
-                let mut cam_pos_wc_v4 = rotate_mat.mat_vec_dot(&Vec4::from(&cam_pos_wc, 1.0));
+                // orbit around the target rather than the world origin
+                let mut cam_pos_wc_v4 = rotate_mat.mat_vec_dot(&Vec4::from(&cam_pos_wc._minus(&target), 1.0));
                 cam_pos_wc_v4.scalar_div_(cam_pos_wc_v4.w());
-                cam_pos_wc.set_x(cam_pos_wc_v4.x());
-                cam_pos_wc.set_y(cam_pos_wc_v4.y());
-                cam_pos_wc.set_z(cam_pos_wc_v4.z());
+                cam_pos_wc = Vec3::from(&cam_pos_wc_v4)._add(&target);
             } else {
                 arc_ball_initialized = true;
                 arc_ball_previous.set_x(normalized_x);
@@ -254,240 +312,122 @@ fn main() {
         {
             match state.keycode
             {
-                VirtualKeyCode::P => {
-                    gouraud_shading = false;
-                    println!("Using Phong Shading");
+                VirtualKeyCode::T | VirtualKeyCode::G | VirtualKeyCode::P => {
+                    shading = match state.keycode {
+                        VirtualKeyCode::T => ShadingMode::Flat,
+                        VirtualKeyCode::G => ShadingMode::Gouraud,
+                        _ => ShadingMode::Phong,
+                    };
+                    renderer.shader = shading.shader();
+                    println!("Using {:?} Shading", shading);
+                }
+                VirtualKeyCode::O => {
+                    save_screenshot = true;
+                }
+                VirtualKeyCode::D => {
+                    depth_view = !depth_view;
+                    println!("Showing {}", if depth_view { "the depth buffer" } else { "colors" });
+                }
+                VirtualKeyCode::M => {
+                    renderer.msaa = match renderer.msaa {
+                        Msaa::X1 => Msaa::X2,
+                        Msaa::X2 => Msaa::X4,
+                        Msaa::X4 => Msaa::X8,
+                        Msaa::X8 => Msaa::X1,
+                    };
+                    println!("Using {}x MSAA", renderer.msaa.samples());
+                }
+                VirtualKeyCode::S => {
+                    renderer.ssaa_factor = match renderer.ssaa_factor {
+                        1 => 2,
+                        2 => 4,
+                        _ => 1,
+                    };
+                    println!("Using {}x SSAA", renderer.ssaa_factor);
+                }
+                VirtualKeyCode::L => {
+                    renderer.ssaa_filter = match renderer.ssaa_filter {
+                        Downsample::Box => Downsample::Lanczos,
+                        Downsample::Lanczos => Downsample::Box,
+                    };
+                    println!("Filtering supersampled frames with {:?}", renderer.ssaa_filter);
+                }
+                VirtualKeyCode::F => {
+                    renderer.fxaa = !renderer.fxaa;
+                    println!("FXAA {}", if renderer.fxaa { "on" } else { "off" });
+                }
+                VirtualKeyCode::C => {
+                    renderer.cull_mode = match renderer.cull_mode {
+                        CullMode::None => CullMode::Back,
+                        CullMode::Back => CullMode::Front,
+                        CullMode::Front => CullMode::None,
+                    };
+                    println!("Culling {:?} faces", renderer.cull_mode);
                 }
-                VirtualKeyCode::G => {
-                    gouraud_shading = true;
-                    println!("Using Gouraud Shading");
+                VirtualKeyCode::B => {
+                    renderer.lighting = match renderer.lighting {
+                        LightingModel::Phong => LightingModel::BlinnPhong,
+                        LightingModel::BlinnPhong => LightingModel::Pbr,
+                        LightingModel::Pbr => LightingModel::Phong,
+                    };
+                    println!("Using {:?} lighting", renderer.lighting);
                 }
                 _ => {}
             }
         }
         state.reset_flags();
-        let camera = Camera::new(cam_pos_wc,
-                                 Vec3::new_xyz(0.0, 0.0, 0.0),
-                                 Vec3::new_xyz(0.0, 1.0, 0.0));
-        let mut light_pos_ec = camera.transformation.mat_vec_dot(&light_pos_wc);
-        light_pos_ec.scalar_div_(light_pos_ec.w());
-        let normal_mat = camera.inverse_transformation.transpose();
-        let mut vertices_ec: Vec<Vertex> = vertices_wc.par_iter().map(|v_wc| {
-            let mut p_ec = camera.transformation.mat_vec_dot(&v_wc.position);
-            p_ec.scalar_div_(p_ec.w());
-            return Vertex {
-                position: p_ec,
-                idx: v_wc.idx,
-            };
-        }).collect();
-        vertices_ec.sort_by(|a, b| a.idx.partial_cmp(&b.idx).unwrap());
-        let mut normal_ec: Vec<Normal> = normals_wc.par_iter().map(|n_wc| {
-            let mut n_ec = normal_mat.mat_vec_dot(&n_wc.vec);
-            n_ec.normalize_();
-            return Normal {
-                vertex_idx: n_wc.vertex_idx,
-                vec: n_ec,
-            };
-        }).collect();
-        normal_ec.sort_by(|a, b| a.vertex_idx.partial_cmp(&b.vertex_idx).unwrap());
-
-        let light_ec;
-        let before_rasterization = now.elapsed().as_millis();
-        let mut fragments;
-        if gouraud_shading
+        renderer.camera = Camera::new(cam_pos_wc, target, up);
+        let stats = renderer.render(&scene, &mut frame);
+        if depth_view
         {
-            light_ec = Light {
-                position: Vec3::from(&light_pos_ec),
-                original_position: Vec3::from(&light_pos_ec),
-                ambient: Vec3::new_rgb(1.0, 1.0, 1.0),
-                diffuse: Vec3::new_rgb(1.0, 1.0, 1.0),
-            };
-            let vertices_colors = gouraud_shade(&vertices_ec, &normal_ec, &light_ec, &silver_material);
-            let triangles_ec = get_triangles(&vertices_ec, &vertices_colors, &mesh);
-            let proj_mat = perspective(FOV_Y, (WIDTH as f32) / (HEIGHT as f32), NEAR, FAR);
-            fragments = rasterization(&triangles_ec, &proj_mat, WIDTH as u32, HEIGHT as u32);
-        } else {
-            light_ec = Light {
-                position: Vec3::from(&light_pos_ec),
-                original_position: Vec3::from(&light_pos_ec),
-                ambient: Vec3::new_rgb(0.3, 0.3, 0.3),
-                diffuse: Vec3::new_rgb(0.7, 0.7, 0.7),
-            };
-            let triangles_ec = get_triangles(&vertices_ec, &normal_ec, &mesh);
-            let proj_mat = perspective(FOV_Y, (WIDTH as f32) / (HEIGHT as f32), NEAR, FAR);
-            fragments = rasterization(&triangles_ec, &proj_mat, WIDTH as u32, HEIGHT as u32);
+            frame = renderer.depth_frame(depth_remap);
         }
-        let mut survived_fragments = Vec::new();
-        while !fragments.is_empty()
+        if save_screenshot
         {
-            let f = fragments.pop().unwrap();
-            if zbuff.update(f.x as usize, f.y as usize, f.z) {
-                survived_fragments.push(f);
+            save_screenshot = false;
+            let screenshots = match depth_view {
+                true => [DEPTH_SCREENSHOT_PNG_PATH, DEPTH_SCREENSHOT_PFM_PATH],
+                false => [SCREENSHOT_PNG_PATH, SCREENSHOT_PFM_PATH]
+            };
+            for path in screenshots.iter()
+            {
+                let saved = match depth_view {
+                    true => image::save_depth(&frame, Path::new(path)),
+                    false => image::save(&frame, Path::new(path))
+                };
+                match saved {
+                    Ok(_) => println!("Saved {}", path),
+                    Err(e) => println!("Failed to save {}: {}", path, e)
+                }
             }
         }
-        let after_rasterization = now.elapsed().as_millis();
-        raster_time_ema = ema_alpha * raster_time_ema + ema_beta * (after_rasterization - before_rasterization) as f32;
-
-
-        let before_shading = now.elapsed().as_millis();
-        let colors: Vec<(XY, Color)> = survived_fragments.par_iter().map(|f| {
-            let color = match gouraud_shading {
-                true => get_gouraud_color(f),
-                false => shade(f, &light_ec, &silver_material)
-            };
-            return (XY(f.x as usize, f.y as usize), color);
-        }).collect();
+        geometry_time_ema = ema_alpha * geometry_time_ema + ema_beta * stats.geometry_ms as f32;
+        raster_time_ema = ema_alpha * raster_time_ema + ema_beta * stats.rasterization_ms as f32;
+        shading_time_ema = ema_alpha * shading_time_ema + ema_beta * stats.shading_ms as f32;
+        post_processing_time_ema = ema_alpha * post_processing_time_ema + ema_beta * stats.post_processing_ms as f32;
 
-        for color in colors.iter()
+        for y in 0..height
         {
-            let xy = &color.0;
-            *frame_buffer_image.index_mut(XY(xy.0, xy.1)) = color.1.clone();
+            for x in 0..width
+            {
+                let [r, g, b, _] = frame.get(x, y);
+                *frame_buffer_image.index_mut(XY(x, y)) = Color::rgb(r, g, b);
+            }
         }
-        let after_shading = now.elapsed().as_millis();
-        shading_time_ema = ema_alpha * shading_time_ema + ema_beta * (after_shading - before_shading) as f32;
 
         if i % every_n_frames == 0 {
             i = 0;
-            if gouraud_shading
-            {
-                println!("\nUsing Gouraud Shading, press P to use Phong Shading");
-            }
-            else {
-                println!("\nUsing Phong Shading, press G to use Gouraud Shading");
-            }
+            println!("\nUsing {:?} Shading with {:?} lighting, press T, G or P to use Flat, Gouraud or Phong Shading, press B to change the lighting model, press O to save a screenshot, press D to toggle the depth view, press M, S, L or F to change anti-aliasing",
+                     shading, renderer.lighting);
+            println!("    Geometry Time EMA {} ms", geometry_time_ema);
             println!("    Rasterization Time EMA {} ms", raster_time_ema);
             println!("    Shading Time EMA {} ms", shading_time_ema);
+            println!("    Post-processing Time EMA {} ms", post_processing_time_ema);
+            println!("    Culled {} of {} triangles ({:?} faces, press C to change)",
+                     stats.culled_triangles, stats.triangles, renderer.cull_mode);
+            println!("    Early-Z rejected {} samples, hierarchical Z skipped up to {} samples",
+                     stats.depth.early_z_rejected, stats.depth.hiz_rejected);
         }
         i += 1;
-        zbuff.reset(f32::MAX);
     });
-    println!("OK");
-}
-
-pub struct ZBuffer
-{
-    depth_buffer: Vec<Vec<f32>>,
-}
-
-impl ZBuffer
-{
-    pub fn new(width: usize, height: usize, init_val: f32) -> Self
-    {
-        let depth_buffer: Vec<Vec<f32>> = (0..width).map(|_| {
-            let col: Vec<f32> = (0..height).map(|_| init_val).collect();
-            return col;
-        }).collect();
-        ZBuffer {
-            depth_buffer
-        }
-    }
-
-    pub fn reset(&mut self, val: f32) {
-        self.depth_buffer.iter_mut().for_each(|col| col.iter_mut().for_each(|depth| *depth = val));
-    }
-
-    pub fn update(&mut self, x: usize, y: usize, val: f32) -> bool
-    {
-        let old = self._get(x, y);
-        return if *old > val
-        {
-            *old = val;
-            true
-        } else {
-            false
-        };
-    }
-
-    pub fn get(&self, x: usize, y: usize) -> f32
-    {
-        unsafe {
-            return *self.depth_buffer.get_unchecked(x).get_unchecked(y);
-        }
-    }
-
-    #[inline]
-    fn _get(&mut self, x: usize, y: usize) -> &mut f32
-    {
-        unsafe {
-            return self.depth_buffer.get_unchecked_mut(x).get_unchecked_mut(y);
-        }
-    }
-}
-
-pub fn get_gouraud_color(fragment: &Fragment) -> Color
-{
-    // println!("{:?}", fragment.normal_ec);
-    let mut color_f = Vec3::from(&fragment.normal_ec);
-    color_f.scalar_mul_(-1.0);
-    return to_color(color_f);
-}
-
-pub fn gouraud_shade(vertices_ec: &Vec<Vertex>, normals_ec: &Vec<Normal>, light: &Light, material: &Material) -> Vec<Normal>
-{
-    assert_eq!(vertices_ec.len(), normals_ec.len());
-    let idxs: Vec<usize> = (0..vertices_ec.len()).collect();
-    let mut vertices_colors: Vec<Normal> = idxs.par_iter().map(|i| {
-        let i = *i;
-        unsafe {
-            let n = normals_ec.get_unchecked(i);
-            let v = vertices_ec.get_unchecked(i);
-            let mut normal_ec = Vec3::from(&n.vec);
-            normal_ec.normalize_();
-            let pos_ec = v.position.clone();
-            let mut light_dir = light.position._minus(&Vec3::from(&pos_ec));
-            light_dir.normalize_();
-            let mut view_dir = Vec3::from(&pos_ec);
-            view_dir.scalar_mul_(-1.0);
-            view_dir.normalize_();
-            let color_f = phong_lighting(&light_dir, &normal_ec, &view_dir, material, light);
-            return Normal {
-                vec: Vec4::from(&color_f, 0.0),
-                vertex_idx: i,
-            };
-        }
-    }).collect();
-    vertices_colors.sort_by(|a, b| a.vertex_idx.partial_cmp(&b.vertex_idx).unwrap());
-    return vertices_colors;
-}
-
-pub fn shade(fragment: &Fragment, light: &Light, material: &Material) -> Color
-{
-    let mut normal_ec = Vec3::from(&fragment.normal_ec);
-    normal_ec.normalize_();
-    let pos_ec = fragment.coord_ec.clone();
-    let mut light_dir = light.position._minus(&Vec3::from(&pos_ec));
-    light_dir.normalize_();
-    let mut view_dir = Vec3::from(&pos_ec);
-    view_dir.scalar_mul_(-1.0);
-    view_dir.normalize_();
-    let color_f = phong_lighting(&light_dir, &normal_ec, &view_dir, material, light);
-    return to_color(color_f);
-}
-
-#[inline]
-fn to_color(mut color: Vec3) -> Color {
-    clamp_(&mut color);
-    color.scalar_mul_(255.);
-    let x = color.r().round();
-    let y = color.g().round();
-    let z = color.b().round();
-    Color::rgb(x as u8, y as u8, z as u8)
-}
-
-#[inline]
-fn clamp_(color: &mut Vec3) {
-    color.set_r(clamp_float(color.r()));
-    color.set_g(clamp_float(color.g()));
-    color.set_b(clamp_float(color.b()));
-}
-
-#[inline]
-fn clamp_float(x: f32) -> f32 {
-    if x < 0. {
-        return 0.;
-    }
-    if x > 1. {
-        return 1.;
-    }
-    x
 }
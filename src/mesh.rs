@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::data::{Add, Cross, Minus, Normalize, ScalarDiv, Vec2, Vec3, Vec4};
+use crate::shader::Varyings;
+use crate::shading::{Normal, Triangle, Vertex};
+
+/// Where the vertex normals of a mesh come from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NormalMode
+{
+    /// Authored normals when the mesh has them, smooth normals otherwise
+    Auto,
+    /// Always rebuild normals by averaging the faces around each vertex
+    Smooth,
+    /// One normal per face, vertices are no longer shared between faces
+    Flat,
+}
+
+/// Flat attribute arrays of one mesh in object space, indexed by `indices` like `tobj` does
+///
+/// `normals` and `texcoords` are either empty or hold one entry per position.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData
+{
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub texcoords: Vec<f32>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData
+{
+    pub fn new(positions: Vec<f32>, indices: Vec<u32>) -> Self
+    {
+        MeshData {
+            positions,
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            indices,
+        }
+    }
+
+    pub fn vertex_num(&self) -> usize
+    {
+        self.positions.len() / 3
+    }
+
+    /// Whether the mesh carries one authored normal per position
+    pub fn has_normals(&self) -> bool
+    {
+        !self.normals.is_empty() && self.normals.len() == self.positions.len()
+    }
+
+    /// Whether the mesh carries one texture coordinate per position
+    pub fn has_texcoords(&self) -> bool
+    {
+        !self.texcoords.is_empty() && self.texcoords.len() / 2 == self.vertex_num()
+    }
+
+    /// Gives every face its own copy of its three vertices, so that nothing is shared between faces
+    pub fn unweld(&self) -> MeshData
+    {
+        let mut mesh = MeshData::default();
+        for (i, idx) in self.indices.iter().enumerate()
+        {
+            let idx = *idx as usize;
+            mesh.positions.extend_from_slice(&self.positions[idx * 3..idx * 3 + 3]);
+            if self.has_normals()
+            {
+                mesh.normals.extend_from_slice(&self.normals[idx * 3..idx * 3 + 3]);
+            }
+            if self.has_texcoords()
+            {
+                mesh.texcoords.extend_from_slice(&self.texcoords[idx * 2..idx * 2 + 2]);
+            }
+            mesh.indices.push(i as u32);
+        }
+        return mesh;
+    }
+}
+
+impl From<tobj::Mesh> for MeshData
+{
+    fn from(mesh: tobj::Mesh) -> Self
+    {
+        MeshData {
+            positions: mesh.positions,
+            normals: mesh.normals,
+            texcoords: mesh.texcoords,
+            indices: mesh.indices,
+        }
+    }
+}
+
+pub fn get_position_os(positions: &Vec<f32>) -> Vec<Vertex>
+{
+    let idxs: Vec<usize> = (0..positions.len()).step_by(3).collect();
+    let mut positions_os: Vec<Vertex> = idxs.par_iter().map(|i| {
+        let i = *i;
+        let vertex_idx = i / 3;
+        unsafe
+            {
+                let x = *positions.get_unchecked(i);
+                let y = *positions.get_unchecked(i + 1);
+                let z = *positions.get_unchecked(i + 2);
+                return Vertex {
+                    position: Vec4::new_xyzw(x, y, z, 1.0),
+                    idx: vertex_idx,
+                };
+            }
+    }).collect();
+    positions_os.sort_by(|a, b| a.idx.partial_cmp(&b.idx).unwrap());
+    return positions_os;
+}
+
+/// Reads authored normals, which are flat xyz triples like the positions
+pub fn get_normals_os(normals: &Vec<f32>) -> Vec<Normal>
+{
+    let normals_os: Vec<Normal> = normals.chunks_exact(3).enumerate().map(|(vertex_idx, n)| {
+        let vn = Vec3::new_xyz(n[0], n[1], n[2]).normalize();
+        return Normal {
+            vertex_idx,
+            vec: Vec4::from(&vn, 0.0),
+        };
+    }).collect();
+    return normals_os;
+}
+
+/// Reads texture coordinates, which are flat uv pairs
+pub fn get_texcoords(texcoords: &Vec<f32>) -> Vec<Vec2>
+{
+    texcoords.chunks_exact(2).map(|uv| Vec2::new_uv(uv[0], uv[1])).collect()
+}
+
+pub fn get_adj_vertices(indices: &Vec<u32>) -> HashMap<usize, Vec<(usize, usize)>>
+{
+    let mut map = HashMap::<usize, Vec<(usize, usize)>>::new();
+    for i in (0..indices.len()).step_by(3)
+    {
+        unsafe {
+            let idx1 = (*indices.get_unchecked(i)) as usize;
+            let idx2 = (*indices.get_unchecked(i + 1)) as usize;
+            let idx3 = (*indices.get_unchecked(i + 2)) as usize;
+            match map.get_mut(&idx1)
+            {
+                None => {
+                    let v = vec![(idx2, idx3)];
+                    map.insert(idx1, v);
+                }
+                Some(vec) => {
+                    vec.push((idx2, idx3));
+                }
+            }
+
+            match map.get_mut(&idx2)
+            {
+                None => {
+                    let v = vec![(idx3, idx1)];
+                    map.insert(idx2, v);
+                }
+                Some(vec) => {
+                    vec.push((idx3, idx1));
+                }
+            }
+
+            match map.get_mut(&idx3)
+            {
+                None => {
+                    let v = vec![(idx1, idx2)];
+                    map.insert(idx3, v);
+                }
+                Some(vec) => {
+                    vec.push((idx1, idx2));
+                }
+            }
+        }
+    }
+    return map;
+}
+
+/// Assembles the indexed triangles, whose varyings hold those of the vertex shader then the texture
+/// coordinates, `varyings` and `texcoords` may be empty
+pub fn get_triangles<'a>(vertices: &'a Vec<Vertex>, varyings: &Vec<Varyings>, texcoords: &Vec<Vec2>,
+                         indices: &Vec<u32>) -> Vec<Triangle<'a>>
+{
+    let idxs: Vec<usize> = (0..indices.len()).step_by(3).collect();
+    let textured = !texcoords.is_empty();
+    let shaded = !varyings.is_empty();
+    let triangles: Vec<Triangle> = idxs.par_iter().map(|i| {
+        let i = *i;
+        unsafe {
+            let idx1 = (*indices.get_unchecked(i)) as usize;
+            let idx2 = (*indices.get_unchecked(i + 1)) as usize;
+            let idx3 = (*indices.get_unchecked(i + 2)) as usize;
+            let mut triangle = Triangle::new(vertices.get_unchecked(idx1), vertices.get_unchecked(idx2),
+                                             vertices.get_unchecked(idx3));
+            if shaded
+            {
+                triangle = triangle.with_varyings(varyings[idx1], varyings[idx2], varyings[idx3]);
+            }
+            if textured
+            {
+                triangle = triangle.with_texcoords(texcoords[idx1], texcoords[idx2], texcoords[idx3]);
+            }
+            return triangle;
+        }
+    }).collect();
+    return triangles;
+}
+
+pub fn get_normals(vertices: &Vec<Vertex>, adj_vertices_map: &HashMap<usize, Vec<(usize, usize)>>) -> Vec<Normal>
+{
+    let mut normals: Vec<Normal> = adj_vertices_map.par_iter().map(|(vertex, adj_point_vertices)| {
+        unsafe {
+            let mut v_p = vertices.get_unchecked(*vertex).position.clone();
+            v_p.scalar_div_(v_p.w());
+            let v_p = Vec3::from(&v_p);
+            let mut vn = Vec3::new(0.0);
+            for adj_vertices in adj_point_vertices.iter()
+            {
+                let mut v1_p = vertices.get_unchecked(adj_vertices.0).position.clone();
+                let mut v2_p = vertices.get_unchecked(adj_vertices.1).position.clone();
+                v1_p.scalar_div_(v1_p.w());
+                v2_p.scalar_div_(v2_p.w());
+
+                let v1_p = Vec3::from(&v1_p);
+                let v2_p = Vec3::from(&v2_p);
+                let v_v1 = v1_p._minus(&v_p);
+                let v_v2 = v2_p._minus(&v_p);
+
+                let mut n = v_v1.cross(&v_v2);
+                n.normalize_();
+                vn.add_(&n);
+            }
+            vn.normalize_();
+            return Normal {
+                vertex_idx: *vertex,
+                vec: Vec4::from(&vn, 0.0),
+            };
+        }
+    }).collect();
+    normals.sort_by(|a, b| a.vertex_idx.partial_cmp(&b.vertex_idx).unwrap());
+    return normals;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn quad() -> MeshData
+    {
+        // two faces folded by 90 degrees along the y axis
+        let mut mesh = MeshData::new(vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+                                     vec![0, 2, 1, 0, 1, 3]);
+        mesh.texcoords = vec![0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0];
+        return mesh;
+    }
+
+    #[test]
+    fn test_unweld_duplicates_shared_vertices() {
+        let mesh = quad();
+        let unwelded = mesh.unweld();
+        assert_eq!(unwelded.vertex_num(), 6);
+        assert_eq!(unwelded.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert!(unwelded.has_texcoords());
+        assert!(!unwelded.has_normals());
+        assert_eq!(&unwelded.positions[3..6], &[1.0, 0.0, 0.0]);
+        assert_eq!(&unwelded.texcoords[10..12], &[1.0, 1.0]);
+
+        let vertices = get_position_os(&unwelded.positions);
+        let normals = get_normals(&vertices, &get_adj_vertices(&unwelded.indices));
+        // every copy of the shared vertices gets the normal of its own face
+        assert_eq!(normals[0].vec.z(), 1.0);
+        assert_eq!(normals[3].vec.x(), 1.0);
+    }
+
+    #[test]
+    fn test_read_authored_attributes() {
+        let normals = get_normals_os(&vec![0.0, 0.0, 2.0, 0.0, 3.0, 0.0]);
+        assert_eq!(normals.len(), 2);
+        assert_eq!(normals[0].vec.z(), 1.0);
+        assert_eq!(normals[1].vec.y(), 1.0);
+        assert_eq!(normals[1].vec.w(), 0.0);
+        let texcoords = get_texcoords(&quad().texcoords);
+        assert_eq!(texcoords.len(), 4);
+        assert_eq!(texcoords[3].v(), 1.0);
+    }
+}
@@ -0,0 +1,718 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use rayon::prelude::*;
+
+use crate::antialiasing::{Downsample, downsample, fxaa};
+use crate::buffer::{DepthFunc, DepthState, FrameBuffer, ZBuffer};
+use crate::clipping::DepthRange;
+use crate::culling::{cull, CullMode, Winding};
+use crate::data::{_Mat, Length, Mat4, Normalize, ScalarDiv, ScalarMul, Transpose, Vec3, Vec4};
+use crate::mesh::get_triangles;
+use crate::scene::Scene;
+use crate::shader::{GouraudShader, Shader, Uniforms, Varyings};
+use crate::shading::*;
+use crate::simd::transform_points;
+use crate::tiling::{bin, DepthStats, split_frame, TILE_SIZE, tile_grid, TileBuffer};
+use crate::transformations::{perspective, perspective_infinite, perspective_reversed, perspective_reversed_infinite};
+
+/// Number of vertices transformed by one task
+const TRANSFORM_CHUNK: usize = 1024;
+
+/// Time spent in each stage of one frame, in milliseconds, and how much geometry went through
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RenderStats
+{
+    /// vertex shading, culling, clipping and triangle setup
+    pub geometry_ms: u128,
+    /// binning and depth testing of the samples in every tile
+    pub rasterization_ms: u128,
+    /// fragment shading and the resolve of the samples into the frame
+    pub shading_ms: u128,
+    /// writing the depth of the tiles back, filtering down supersampled frames and FXAA
+    pub post_processing_ms: u128,
+    /// triangles of the whole scene
+    pub triangles: usize,
+    /// triangles dropped by back-face or frustum culling before rasterization
+    pub culled_triangles: usize,
+    pub depth: DepthStats,
+}
+
+/// How depths are turned into gray levels, black at the near plane and white at the far plane
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DepthRemap
+{
+    /// proportional to the distance from the camera
+    Linear,
+    /// the depth buffer values as they are, which spend most gray levels close to the near plane
+    NonLinear,
+}
+
+/// Entries of a projection that turn distances along the view direction into depth buffer values
+/// and back
+#[derive(Debug, Copy, Clone)]
+pub struct DepthMapping
+{
+    m22: f32,
+    m23: f32,
+}
+
+impl DepthMapping
+{
+    pub fn new(projection: &Mat4) -> Self
+    {
+        DepthMapping { m22: projection._get_entry(2, 2), m23: projection._get_entry(2, 3) }
+    }
+
+    /// Distance along the view direction of a point stored as `depth` in the depth buffer
+    #[inline]
+    pub fn linear_depth(&self, depth: f32) -> f32
+    {
+        // NDC depth is -m22 - m23 / z_ec for every projection
+        return self.m23 / (depth + self.m22);
+    }
+
+    /// Depth stored in the depth buffer for a point at `distance` along the view direction
+    #[inline]
+    pub fn buffer_depth(&self, distance: f32) -> f32
+    {
+        return self.m23 / distance - self.m22;
+    }
+}
+
+/// Renders frames of a `Scene` into a `FrameBuffer` without needing a window
+pub struct Renderer
+{
+    width: usize,
+    height: usize,
+    zbuffer: ZBuffer,
+    pub camera: Camera,
+    /// lights in world coordinates
+    pub lights: Vec<Light>,
+    pub ambient_light: Vec3,
+    /// how every object reflects the lights
+    pub lighting: LightingModel,
+    /// vertex and fragment stages of every object
+    pub shader: Arc<dyn Shader>,
+    pub cull_mode: CullMode,
+    pub front_face: Winding,
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    /// depth test in the conventional direction, where nearer is smaller, mirrored with reversed-Z
+    pub depth_func: DepthFunc,
+    pub depth_write: bool,
+    /// map the near plane to depth 1 and the far plane to 0
+    pub reversed_z: bool,
+    /// ignore `far` and put the far plane at infinity
+    pub infinite_far: bool,
+    /// distance shown white by linear depth images instead of the far plane, needed with `infinite_far`
+    pub depth_far: Option<f32>,
+    /// samples per pixel of multisample anti-aliasing
+    pub msaa: Msaa,
+    /// frames are rendered this many times wider and higher, then filtered down with `ssaa_filter`
+    pub ssaa_factor: usize,
+    pub ssaa_filter: Downsample,
+    /// smooth the edges of the final frame with FXAA
+    pub fxaa: bool,
+}
+
+impl Renderer
+{
+    pub fn new(width: usize, height: usize, fov_y: f32, near: f32, far: f32, camera: Camera, lights: Vec<Light>) -> Self
+    {
+        Renderer {
+            width,
+            height,
+            zbuffer: ZBuffer::new(width, height, DepthState::default().clear),
+            camera,
+            lights,
+            ambient_light: Vec3::new(0.3),
+            lighting: LightingModel::Phong,
+            shader: Arc::new(GouraudShader),
+            cull_mode: CullMode::Back,
+            front_face: Winding::Ccw,
+            fov_y,
+            near,
+            far,
+            depth_func: DepthFunc::Less,
+            depth_write: true,
+            reversed_z: false,
+            infinite_far: false,
+            depth_far: None,
+            msaa: Msaa::X1,
+            ssaa_factor: 1,
+            ssaa_filter: Downsample::Box,
+            fxaa: false,
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize
+    {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize
+    {
+        self.height
+    }
+
+    /// Changes the size of the frames to render, e.g. when the window is resized
+    pub fn resize(&mut self, width: usize, height: usize)
+    {
+        self.width = width;
+        self.height = height;
+        self.zbuffer.resize(width * self.ssaa_factor, height * self.ssaa_factor, self.depth_state().clear);
+    }
+
+    pub fn projection(&self) -> Mat4
+    {
+        let aspect = (self.width as f32) / (self.height as f32);
+        match (self.reversed_z, self.infinite_far) {
+            (false, false) => perspective(self.fov_y, aspect, self.near, self.far),
+            (false, true) => perspective_infinite(self.fov_y, aspect, self.near),
+            (true, false) => perspective_reversed(self.fov_y, aspect, self.near, self.far),
+            (true, true) => perspective_reversed_infinite(self.fov_y, aspect, self.near),
+        }
+    }
+
+    /// Depth test of the frame, the buffer is cleared to the far plane
+    pub fn depth_state(&self) -> DepthState
+    {
+        return match self.reversed_z {
+            true => DepthState { func: self.depth_func.reversed(), write: self.depth_write, clear: 0.0 },
+            false => DepthState { func: self.depth_func, write: self.depth_write, clear: 1.0 },
+        };
+    }
+
+    /// Mapping between distances and depth buffer values of the current projection
+    pub fn depth_mapping(&self) -> DepthMapping
+    {
+        DepthMapping::new(&self.projection())
+    }
+
+    /// The depth buffer of the last frame as a grayscale image, from black at `near` to white at
+    /// the far plane of the projection, or at `depth_far` for linear images
+    ///
+    /// Pixels that nothing was drawn to are white, also with an infinite far plane. With
+    /// multi- or supersampling, each pixel shows its first sample.
+    pub fn depth_frame(&self, remap: DepthRemap) -> FrameBuffer
+    {
+        let clear = self.depth_state().clear;
+        let far = if self.infinite_far { f32::INFINITY } else { self.far };
+        let mapping = self.depth_mapping();
+        // the depth buffer value reaches a finite limit at an infinite distance
+        let (depth_near, depth_far) = (mapping.buffer_depth(self.near), mapping.buffer_depth(far));
+        let linear_far = self.depth_far.unwrap_or(far);
+        assert!(remap != DepthRemap::Linear || linear_far.is_finite(),
+                "linear depth images of an infinite far plane need a finite depth_far");
+        let mut frame = FrameBuffer::new(self.width, self.height);
+        // the buffer is as large as the supersampled frame it was rendered with
+        let factor = self.zbuffer.width() / self.width;
+        for y in 0..self.height
+        {
+            for x in 0..self.width
+            {
+                let depth = self.zbuffer.get(x * factor, y * factor);
+                let gray = if depth == clear {
+                    1.0
+                } else {
+                    match remap {
+                        DepthRemap::Linear => (mapping.linear_depth(depth) - self.near) / (linear_far - self.near),
+                        DepthRemap::NonLinear => (depth - depth_near) / (depth_far - depth_near),
+                    }
+                };
+                frame.set(x, y, Vec3::new(gray.max(0.0).min(1.0)));
+            }
+        }
+        return frame;
+    }
+
+    fn depth_range(&self) -> DepthRange
+    {
+        return match self.reversed_z {
+            true => DepthRange::ZeroToOne,
+            false => DepthRange::MinusOneToOne,
+        };
+    }
+
+    /// Renders one frame of every object in the scene
+    ///
+    /// The frame is cleared to black first, so `frame` may be reused across calls.
+    pub fn render(&mut self, scene: &Scene, frame: &mut FrameBuffer) -> RenderStats
+    {
+        assert_eq!(frame.width(), self.width);
+        assert_eq!(frame.height(), self.height);
+        frame.clear(Vec3::new(0.0));
+        let depth_state = self.depth_state();
+        let depth_range = self.depth_range();
+        // with supersampling, the scene is rendered into a larger frame that is filtered down at the end
+        let factor = self.ssaa_factor;
+        let mut supersampled = match factor {
+            1 => None,
+            _ => Some(FrameBuffer::new(self.width * factor, self.height * factor)),
+        };
+        let target = match supersampled.as_mut() {
+            Some(supersampled) => supersampled,
+            None => &mut *frame,
+        };
+        if self.zbuffer.samples() != self.msaa.samples()
+        {
+            self.zbuffer.set_samples(self.msaa.samples(), depth_state.clear);
+        }
+        if self.zbuffer.width() != target.width() || self.zbuffer.height() != target.height()
+        {
+            self.zbuffer.resize(target.width(), target.height(), depth_state.clear);
+        }
+        self.zbuffer.reset(depth_state.clear);
+
+        let camera = &self.camera;
+        let lights_ec: Vec<Light> = self.lights.iter().map(|l| l.transformed(&camera.transformation)).collect();
+        let uniforms: Vec<Uniforms> = scene.objects.iter().map(|object| Uniforms {
+            lights: &lights_ec,
+            ambient_light: self.ambient_light,
+            lighting: self.lighting,
+            material: &object.material,
+            diffuse_texture: object.diffuse_texture.as_deref(),
+        }).collect();
+        let shader = self.shader.as_ref();
+        let proj_mat = self.projection();
+
+        let mut geometry_ms = 0;
+        let mut triangles = 0;
+        let mut culled_triangles = 0;
+        let (width, height) = (target.width() as u32, target.height() as u32);
+        // triangles of every object, clipped and projected to the screen
+        let mut screen_triangles: Vec<ScreenTriangle> = Vec::new();
+        for (object_idx, object) in scene.objects.iter().enumerate()
+        {
+            let before_geometry = Instant::now();
+            let (vertices_ec, normal_ec) = self.to_eye_coordinates(object.vertices_wc(), object.normals_wc());
+            let varyings: Vec<Varyings> = match shader.per_face() {
+                true => Vec::new(),
+                false => vertices_ec.par_iter().zip(normal_ec.par_iter())
+                    .map(|(v, n)| {
+                        let mut normal = Vec3::from(&n.vec);
+                        if normal.get_length() > 0.0
+                        {
+                            normal.normalize_();
+                        }
+                        return shader.shade_vertex(&v.position, &normal, &uniforms[object_idx]);
+                    })
+                    .collect()
+            };
+            let triangles_ec = get_triangles(&vertices_ec, &varyings, object.texcoords(), object.indices());
+            triangles += triangles_ec.len();
+            let (mut triangles_ec, cull_stats) = cull(triangles_ec, &proj_mat, depth_range, self.cull_mode, self.front_face);
+            culled_triangles += cull_stats.total();
+            if shader.per_face()
+            {
+                let front_face = self.front_face;
+                triangles_ec = triangles_ec.into_par_iter().map(|t| {
+                    let mut normal = match front_face {
+                        Winding::Ccw => t.ccw_normal(),
+                        Winding::Cw => t.ccw_normal().scalar_mul(-1.0),
+                    };
+                    normal.normalize_();
+                    let varyings = shader.shade_vertex(&t.centroid(), &normal, &uniforms[object_idx]);
+                    return t.with_face_varyings(varyings);
+                }).collect();
+            }
+            let mut object_triangles = setup_triangles(&triangles_ec, &proj_mat, depth_range, width, height, object_idx);
+            let polygon_offset = match self.reversed_z {
+                true => object.polygon_offset.reversed(),
+                false => object.polygon_offset
+            };
+            object_triangles.iter_mut().for_each(|t| t.offset_depth(&polygon_offset));
+            screen_triangles.append(&mut object_triangles);
+            geometry_ms += before_geometry.elapsed().as_millis();
+        }
+
+        // every tile is rasterized by one task that owns its depth and its coverage
+        let before_rasterization = Instant::now();
+        let bins = bin(&screen_triangles, width, height);
+        let tile_buffers: Vec<TileBuffer> = split_frame(width, height).into_par_iter().zip(bins.par_iter())
+            .map(|(tile, triangle_idxs)| TileBuffer::rasterize(tile, triangle_idxs, &screen_triangles, &depth_state, self.msaa))
+            .collect();
+        let rasterization_ms = before_rasterization.elapsed().as_millis();
+        let depth = tile_buffers.iter().fold(DepthStats::default(), |stats, buffer| stats.merge(&buffer.stats));
+
+        // only fragments still covering a sample are shaded, once per pixel, and the samples are resolved
+        // against the black background
+        let before_shading = Instant::now();
+        let tile_colors: Vec<Vec<Option<Vec3>>> = tile_buffers.par_iter().map(|buffer| {
+            buffer.resolve(&screen_triangles, Vec3::new(0.0), |object_idx, f| shader.shade_fragment(f, &uniforms[object_idx]))
+        }).collect();
+        for (buffer, colors) in tile_buffers.iter().zip(tile_colors.iter())
+        {
+            for ((x, y), color) in buffer.tile.pixels().zip(colors.iter())
+            {
+                if let Some(color) = color
+                {
+                    target.set(x as usize, y as usize, *color);
+                }
+            }
+        }
+        let shading_ms = before_shading.elapsed().as_millis();
+
+        // every row of tiles owns one band of the depth buffer
+        let before_post_processing = Instant::now();
+        let (columns, _) = tile_grid(width, height);
+        self.zbuffer.bands_mut(TILE_SIZE as usize).into_par_iter().zip(tile_buffers.par_chunks(columns as usize))
+            .for_each(|(mut band, tile_row)| {
+                for buffer in tile_row.iter()
+                {
+                    for ((x, y), depths) in buffer.tile.pixels().zip(buffer.depth.chunks(buffer.samples))
+                    {
+                        for (sample, depth) in depths.iter().enumerate()
+                        {
+                            band.set_sample(x as usize, y as usize, sample, *depth);
+                        }
+                    }
+                }
+            });
+        if let Some(supersampled) = &supersampled
+        {
+            downsample(supersampled, frame, self.ssaa_filter);
+        }
+        if self.fxaa
+        {
+            *frame = fxaa(frame);
+        }
+        let post_processing_ms = before_post_processing.elapsed().as_millis();
+
+        return RenderStats {
+            geometry_ms,
+            rasterization_ms,
+            shading_ms,
+            post_processing_ms,
+            triangles,
+            culled_triangles,
+            depth,
+        };
+    }
+
+    fn to_eye_coordinates(&self, vertices_wc: &Vec<Vertex>, normals_wc: &Vec<Normal>) -> (Vec<Vertex>, Vec<Normal>)
+    {
+        let camera = &self.camera;
+        let normal_mat = camera.inverse_transformation.transpose();
+        let positions_wc: Vec<Vec4> = vertices_wc.iter().map(|v_wc| v_wc.position).collect();
+        let positions_ec: Vec<Vec4> = positions_wc.par_chunks(TRANSFORM_CHUNK)
+            .flat_map(|chunk| transform_points(&camera.transformation, chunk))
+            .collect();
+        let mut vertices_ec: Vec<Vertex> = vertices_wc.par_iter().zip(positions_ec.into_par_iter()).map(|(v_wc, mut p_ec)| {
+            p_ec.scalar_div_(p_ec.w());
+            return Vertex {
+                position: p_ec,
+                idx: v_wc.idx,
+            };
+        }).collect();
+        vertices_ec.sort_by(|a, b| a.idx.partial_cmp(&b.idx).unwrap());
+        let directions_wc: Vec<Vec4> = normals_wc.iter().map(|n_wc| n_wc.vec).collect();
+        let directions_ec: Vec<Vec4> = directions_wc.par_chunks(TRANSFORM_CHUNK)
+            .flat_map(|chunk| transform_points(&normal_mat, chunk))
+            .collect();
+        // computed normals skip vertices that no face uses, so they are put back in place by vertex
+        // and the unused vertices get a zero normal
+        let mut normal_ec: Vec<Normal> = (0..vertices_ec.len())
+            .map(|vertex_idx| Normal { vec: Vec4::new(0.0), vertex_idx })
+            .collect();
+        for (n_wc, mut n_ec) in normals_wc.iter().zip(directions_ec.into_iter())
+        {
+            n_ec.normalize_();
+            normal_ec[n_wc.vertex_idx].vec = n_ec;
+        }
+        return (vertices_ec, normal_ec);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::buffer::PolygonOffset;
+    use crate::mesh::{MeshData, NormalMode};
+    use crate::scene::SceneObject;
+    use crate::shader::{FragmentShader, ShadingMode, VertexShader};
+    use crate::texture::{Filter, Texture};
+
+    use super::*;
+
+    fn quad_renderer(shading: ShadingMode) -> Renderer
+    {
+        let camera = Camera::new(Vec3::new_xyz(0.0, 0.0, 3.0),
+                                 Vec3::new_xyz(0.0, 0.0, 0.0),
+                                 Vec3::new_xyz(0.0, 1.0, 0.0));
+        let mut renderer = Renderer::new(32, 32, std::f32::consts::FRAC_PI_2, 0.1, 10.0,
+                                         camera, vec![Light::point(Vec3::new_xyz(0.0, 0.0, 5.0))]);
+        renderer.shader = shading.shader();
+        return renderer;
+    }
+
+    fn material(diffuse: Vec3) -> Material
+    {
+        Material::new(Vec3::new_rgb(0.1, 0.1, 0.2), diffuse, Vec3::new_rgb(1.0, 1.0, 1.0), Vec3::new_rgb(0.5, 0.5, 0.5),
+                      16.0)
+    }
+
+    /// A square facing the camera, with texture coordinates spanning the whole texture
+    fn quad(x_min: f32, x_max: f32, diffuse: Vec3) -> SceneObject
+    {
+        let positions = vec![
+            x_min, -0.5, 0.0, x_max, -0.5, 0.0, x_max, 0.5, 0.0, x_min, 0.5, 0.0,
+        ];
+        let mut mesh = MeshData::new(positions, vec![0, 1, 2, 0, 2, 3]);
+        mesh.texcoords = vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+        return SceneObject::new("quad".to_string(), mesh, Mat4::identity(), material(diffuse), NormalMode::Auto);
+    }
+
+    #[test]
+    fn test_render_headless() {
+        let mut scene = Scene::new();
+        scene.add(quad(-0.5, 0.5, Vec3::new_rgb(0.5, 0.5, 0.6)));
+        for shading in [ShadingMode::Flat, ShadingMode::Gouraud, ShadingMode::Phong].iter() {
+            let mut renderer = quad_renderer(*shading);
+            let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+            let stats = renderer.render(&scene, &mut frame);
+            assert_eq!((stats.triangles, stats.culled_triangles), (2, 0));
+            assert_ne!(frame.get(16, 16), [0, 0, 0, 255]);
+            assert_eq!(frame.get(0, 0), [0, 0, 0, 255]);
+            assert_eq!(frame.get(31, 31), [0, 0, 0, 255]);
+        }
+    }
+
+    #[test]
+    fn test_render_culls_back_faces() {
+        let mut scene = Scene::new();
+        scene.add(quad(-0.5, 0.5, Vec3::new_rgb(0.5, 0.5, 0.6)));
+        let mut renderer = quad_renderer(ShadingMode::Phong);
+        // seen from behind, the quad is only drawn without culling
+        renderer.camera = Camera::new(Vec3::new_xyz(0.0, 0.0, -3.0), Vec3::new(0.0), Vec3::new_xyz(0.0, 1.0, 0.0));
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        let stats = renderer.render(&scene, &mut frame);
+        assert_eq!(stats.culled_triangles, 2);
+        assert_eq!(frame.get(16, 16), [0, 0, 0, 255]);
+        renderer.cull_mode = CullMode::None;
+        let stats = renderer.render(&scene, &mut frame);
+        assert_eq!(stats.culled_triangles, 0);
+        assert_ne!(frame.get(16, 16), [0, 0, 0, 255]);
+        renderer.cull_mode = CullMode::Front;
+        renderer.front_face = Winding::Cw;
+        assert_eq!(renderer.render(&scene, &mut frame).culled_triangles, 2);
+    }
+
+    #[test]
+    fn test_render_objects_with_their_own_material() {
+        let mut scene = Scene::new();
+        scene.add(quad(-1.0, -0.1, Vec3::new_rgb(1.0, 0.0, 0.0)));
+        scene.add(quad(0.1, 1.0, Vec3::new_rgb(0.0, 1.0, 0.0)));
+        let mut renderer = quad_renderer(ShadingMode::Phong);
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        renderer.render(&scene, &mut frame);
+        let left = frame.get(13, 16);
+        let right = frame.get(19, 16);
+        assert!(left[0] > left[1]);
+        assert!(right[1] > right[0]);
+    }
+
+    #[test]
+    fn test_render_textured_object() {
+        let mut texture = Texture::new(2, 1, vec![Vec3::new_rgb(1.0, 0.0, 0.0), Vec3::new_rgb(0.0, 1.0, 0.0)]);
+        texture.filter = Filter::Nearest;
+        let mut object = quad(-1.0, 1.0, Vec3::new(1.0));
+        object.diffuse_texture = Some(Arc::new(texture));
+        let mut scene = Scene::new();
+        scene.add(object);
+        for shading in [ShadingMode::Flat, ShadingMode::Gouraud, ShadingMode::Phong].iter() {
+            let mut renderer = quad_renderer(*shading);
+            let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+            renderer.render(&scene, &mut frame);
+            let left = frame.get(12, 16);
+            let right = frame.get(20, 16);
+            assert!(left[0] > left[1]);
+            assert!(right[1] > right[0]);
+        }
+    }
+
+    #[test]
+    fn test_render_coplanar_decal() {
+        let mut scene = Scene::new();
+        scene.add(quad(-1.0, 1.0, Vec3::new_rgb(1.0, 0.0, 0.0)));
+        scene.add(quad(-0.5, 0.5, Vec3::new_rgb(0.0, 1.0, 0.0)));
+        let mut renderer = quad_renderer(ShadingMode::Phong);
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        let decal_shown = |renderer: &mut Renderer, scene: &Scene, frame: &mut FrameBuffer| {
+            renderer.render(scene, frame);
+            let center = frame.get(16, 16);
+            return center[1] > center[0];
+        };
+        // both quads face the camera at the same depth, so the decal loses a strict test
+        assert!(!decal_shown(&mut renderer, &scene, &mut frame));
+        renderer.depth_func = DepthFunc::LessEqual;
+        assert!(decal_shown(&mut renderer, &scene, &mut frame));
+        renderer.depth_func = DepthFunc::Less;
+        scene.objects[1].polygon_offset = PolygonOffset { factor: -1.0, units: -1.0 };
+        for (reversed_z, infinite_far) in [(false, false), (false, true), (true, false), (true, true)].iter()
+        {
+            renderer.reversed_z = *reversed_z;
+            renderer.infinite_far = *infinite_far;
+            assert!(decal_shown(&mut renderer, &scene, &mut frame));
+            assert_eq!(frame.get(16, 6), [0, 0, 0, 255]);
+        }
+        // without depth writes, the last object drawn wins
+        scene.objects[1].polygon_offset = PolygonOffset::default();
+        renderer.depth_write = false;
+        assert!(decal_shown(&mut renderer, &scene, &mut frame));
+    }
+
+    #[test]
+    fn test_depth_frame() {
+        let mut scene = Scene::new();
+        scene.add(quad(-1.0, 1.0, Vec3::new(1.0)));
+        // the quad is 3 units away, 1/3 of the way from near = 1 to far = 7
+        let mut renderer = quad_renderer(ShadingMode::Phong);
+        renderer.near = 1.0;
+        renderer.far = 7.0;
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        for (reversed_z, infinite_far) in [(false, false), (false, true), (true, false), (true, true)].iter()
+        {
+            renderer.reversed_z = *reversed_z;
+            renderer.infinite_far = *infinite_far;
+            // the linear ramp needs an end when the far plane is at infinity
+            renderer.depth_far = if *infinite_far { Some(7.0) } else { None };
+            renderer.render(&scene, &mut frame);
+            let depth = renderer.depth_frame(DepthRemap::Linear);
+            assert!((depth.get_f32(16, 16).r() - 1.0 / 3.0).abs() < 1e-4);
+            assert_eq!(depth.get_f32(16, 2).r(), 1.0);
+            let mapping = renderer.depth_mapping();
+            assert!((mapping.linear_depth(mapping.buffer_depth(5.0)) - 5.0).abs() < 1e-4);
+            // the near plane maps to the near end of the depth range, infinity to the far end
+            let (depth_near, depth_far) = if *reversed_z { (1.0, 0.0) } else { (-1.0, 1.0) };
+            assert!((mapping.buffer_depth(1.0) - depth_near).abs() < 1e-5);
+            if *infinite_far
+            {
+                assert!((mapping.buffer_depth(f32::INFINITY) - depth_far).abs() < 1e-5);
+            }
+            // non-linear depth crowds towards the far end
+            let depth = renderer.depth_frame(DepthRemap::NonLinear);
+            assert!(depth.get_f32(16, 16).r() > 0.5);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_linear_depth_frame_needs_finite_far() {
+        let mut scene = Scene::new();
+        scene.add(quad(-1.0, 1.0, Vec3::new(1.0)));
+        let mut renderer = quad_renderer(ShadingMode::Phong);
+        renderer.infinite_far = true;
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        renderer.render(&scene, &mut frame);
+        renderer.depth_frame(DepthRemap::Linear);
+    }
+
+    #[test]
+    fn test_render_anti_aliased() {
+        let mut scene = Scene::new();
+        scene.add(quad(-0.5, 0.5, Vec3::new(1.0)));
+        // the right edge of the quad crosses pixel 18 two thirds of the way, right of its center
+        let mut renderer = quad_renderer(ShadingMode::Phong);
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        renderer.render(&scene, &mut frame);
+        let (inside, edge) = (frame.get_f32(16, 16).r(), frame.get_f32(18, 16).r());
+        assert!(edge > 0.0);
+        assert_eq!(frame.get(19, 16), [0, 0, 0, 255]);
+
+        let partly_covered = |renderer: &mut Renderer, frame: &mut FrameBuffer| {
+            renderer.render(&scene, frame);
+            assert_eq!((frame.width(), frame.height()), (32, 32));
+            let blended = frame.get_f32(18, 16).r();
+            return blended > 0.1 * edge && blended < 0.9 * edge;
+        };
+        renderer.msaa = Msaa::X4;
+        assert!(partly_covered(&mut renderer, &mut frame));
+        renderer.msaa = Msaa::X1;
+        renderer.ssaa_factor = 2;
+        assert!(partly_covered(&mut renderer, &mut frame));
+        renderer.ssaa_filter = Downsample::Lanczos;
+        assert!(partly_covered(&mut renderer, &mut frame));
+        // the depth buffer follows the supersampled frame
+        assert_eq!(renderer.depth_frame(DepthRemap::Linear).get(0, 0), [255, 255, 255, 255]);
+        renderer.ssaa_factor = 1;
+        renderer.fxaa = true;
+        renderer.render(&scene, &mut frame);
+        assert!(frame.get_f32(16, 16).r() > 0.9 * inside);
+        assert_eq!(frame.get(2, 2), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_render_flat_shading() {
+        // authored normals bend towards x on the right, while the faces look at the camera
+        let mut mesh = MeshData::new(vec![-1.0, -0.5, 0.0, 1.0, -0.5, 0.0, 1.0, 0.5, 0.0, -1.0, 0.5, 0.0],
+                                     vec![0, 1, 2, 0, 2, 3]);
+        mesh.normals = vec![0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        let mut scene = Scene::new();
+        scene.add(SceneObject::new("quad".to_string(), mesh, Mat4::identity(), material(Vec3::new(0.5)), NormalMode::Auto));
+        // both pixels are inside of the first triangle
+        let (near, far) = ((17, 16), (20, 16));
+        let mut renderer = quad_renderer(ShadingMode::Gouraud);
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        renderer.render(&scene, &mut frame);
+        assert_ne!(frame.get(near.0, near.1), frame.get(far.0, far.1));
+        renderer.shader = ShadingMode::Flat.shader();
+        renderer.render(&scene, &mut frame);
+        assert_eq!(frame.get(near.0, near.1), frame.get(far.0, far.1));
+        assert_ne!(frame.get(near.0, near.1), [0, 0, 0, 255]);
+    }
+
+    /// Colors fragments with their normal, carried as varyings
+    struct NormalShader;
+
+    impl VertexShader for NormalShader
+    {
+        fn shade_vertex(&self, _position_ec: &Vec4, normal_ec: &Vec3, _uniforms: &Uniforms) -> Varyings
+        {
+            let mut varyings = Varyings::new();
+            varyings.push_vec3(normal_ec).unwrap();
+            return varyings;
+        }
+    }
+
+    impl FragmentShader for NormalShader
+    {
+        fn shade_fragment(&self, fragment: &Fragment, _uniforms: &Uniforms) -> Vec3
+        {
+            fragment.varyings.vec3(0)
+        }
+    }
+
+    #[test]
+    fn test_render_mesh_with_unused_vertex() {
+        // the first vertex belongs to no face, so it has no computed normal
+        let positions = vec![
+            9.0, 9.0, 9.0, -0.5, -0.5, 0.0, 0.5, -0.5, 0.0, 0.5, 0.5, 0.0, -0.5, 0.5, 0.0,
+        ];
+        let mesh = MeshData::new(positions, vec![1, 2, 3, 1, 3, 4]);
+        let mut scene = Scene::new();
+        scene.add(SceneObject::new("quad".to_string(), mesh, Mat4::identity(), material(Vec3::new(1.0)), NormalMode::Auto));
+        let mut renderer = quad_renderer(ShadingMode::Gouraud);
+        renderer.shader = Arc::new(NormalShader);
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        renderer.render(&scene, &mut frame);
+        // the normals of the used vertices stay with them
+        assert_eq!(frame.get(16, 16), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_render_custom_shader() {
+        let mut scene = Scene::new();
+        scene.add(quad(-0.5, 0.5, Vec3::new(1.0)));
+        let mut renderer = quad_renderer(ShadingMode::Gouraud);
+        renderer.shader = Arc::new(NormalShader);
+        let mut frame = FrameBuffer::new(renderer.width(), renderer.height());
+        renderer.render(&scene, &mut frame);
+        // the quad faces the camera
+        assert_eq!(frame.get(16, 16), [0, 0, 255, 255]);
+        assert_eq!(frame.get(2, 2), [0, 0, 0, 255]);
+    }
+}
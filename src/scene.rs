@@ -0,0 +1,306 @@
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::buffer::PolygonOffset;
+use crate::data::{Inverse, Mat4, MatVecDot, Normalize, Transpose, Vec2, Vec3, Vec4};
+use crate::mesh::{get_adj_vertices, get_normals, get_normals_os, get_position_os, get_texcoords, MeshData, NormalMode};
+use crate::shading::{Material, Normal, Vertex};
+use crate::texture::Texture;
+
+/// One mesh placed in the world with its own transformation and material
+pub struct SceneObject
+{
+    pub name: String,
+    pub material: Material,
+    /// Loaded image of `material.diffuse_texture`, shared by the objects using the same file
+    pub diffuse_texture: Option<Arc<Texture>>,
+    /// pulls decals and overlays in front of the surface they lie on
+    pub polygon_offset: PolygonOffset,
+    mesh: MeshData,
+    normal_mode: NormalMode,
+    vertices_os: Vec<Vertex>,
+    /// authored normals, `None` when normals are computed from the positions
+    normals_os: Option<Vec<Normal>>,
+    texcoords: Vec<Vec2>,
+    indices: Vec<u32>,
+    transformation: Mat4,
+    vertices_wc: Vec<Vertex>,
+    normals_wc: Vec<Normal>,
+}
+
+impl SceneObject
+{
+    pub fn new(name: String, mesh: MeshData, transformation: Mat4, material: Material, normal_mode: NormalMode) -> Self
+    {
+        let mut object = SceneObject {
+            name,
+            material,
+            diffuse_texture: None,
+            polygon_offset: PolygonOffset::default(),
+            mesh,
+            normal_mode,
+            vertices_os: Vec::new(),
+            normals_os: None,
+            texcoords: Vec::new(),
+            indices: Vec::new(),
+            transformation,
+            vertices_wc: Vec::new(),
+            normals_wc: Vec::new(),
+        };
+        object.update_geometry();
+        return object;
+    }
+
+    #[inline]
+    pub fn normal_mode(&self) -> NormalMode
+    {
+        self.normal_mode
+    }
+
+    /// Switches between authored, smooth and flat normals
+    pub fn set_normal_mode(&mut self, normal_mode: NormalMode)
+    {
+        self.normal_mode = normal_mode;
+        self.update_geometry();
+    }
+
+    #[inline]
+    pub fn transformation(&self) -> &Mat4
+    {
+        &self.transformation
+    }
+
+    /// Replaces the object to world transformation
+    pub fn set_transformation(&mut self, transformation: Mat4)
+    {
+        self.transformation = transformation;
+        self.update_world_geometry();
+    }
+
+    #[inline]
+    pub fn vertices_os(&self) -> &Vec<Vertex>
+    {
+        &self.vertices_os
+    }
+
+    #[inline]
+    pub fn vertices_wc(&self) -> &Vec<Vertex>
+    {
+        &self.vertices_wc
+    }
+
+    #[inline]
+    pub fn normals_wc(&self) -> &Vec<Normal>
+    {
+        &self.normals_wc
+    }
+
+    /// One texture coordinate per vertex, empty if the mesh has none
+    #[inline]
+    pub fn texcoords(&self) -> &Vec<Vec2>
+    {
+        &self.texcoords
+    }
+
+    /// Whether the normals come from the mesh file rather than from the positions
+    #[inline]
+    pub fn has_authored_normals(&self) -> bool
+    {
+        self.normals_os.is_some()
+    }
+
+    #[inline]
+    pub fn indices(&self) -> &Vec<u32>
+    {
+        &self.indices
+    }
+
+    pub fn triangle_num(&self) -> usize
+    {
+        self.indices.len() / 3
+    }
+
+    fn update_geometry(&mut self)
+    {
+        // flat faces must not share vertices, otherwise each vertex gets the average of its faces
+        let unwelded;
+        let mesh = match self.normal_mode {
+            NormalMode::Flat => {
+                unwelded = self.mesh.unweld();
+                &unwelded
+            }
+            NormalMode::Auto | NormalMode::Smooth => &self.mesh,
+        };
+        self.vertices_os = get_position_os(&mesh.positions);
+        self.normals_os = match self.normal_mode {
+            NormalMode::Auto if mesh.has_normals() => Some(get_normals_os(&mesh.normals)),
+            _ => None,
+        };
+        self.texcoords = if mesh.has_texcoords() { get_texcoords(&mesh.texcoords) } else { Vec::new() };
+        self.indices = mesh.indices.clone();
+        self.update_world_geometry();
+    }
+
+    fn update_world_geometry(&mut self)
+    {
+        let transformation = &self.transformation;
+        self.vertices_wc = self.vertices_os.par_iter().map(|v_os| Vertex {
+            position: transformation.mat_vec_dot(&v_os.position),
+            idx: v_os.idx,
+        }).collect();
+        self.normals_wc = match &self.normals_os {
+            Some(normals_os) => {
+                let normal_transformation = transformation.inverse().transpose();
+                normals_os.par_iter().map(|n_os| {
+                    let n_wc = Vec3::from(&normal_transformation.mat_vec_dot(&n_os.vec)).normalize();
+                    return Normal {
+                        vec: Vec4::from(&n_wc, 0.0),
+                        vertex_idx: n_os.vertex_idx,
+                    };
+                }).collect()
+            }
+            // computed normals are rebuilt from the world positions, so any affine transformation is fine
+            None => get_normals(&self.vertices_wc, &get_adj_vertices(&self.indices)),
+        };
+    }
+}
+
+/// Everything that is drawn in a frame
+pub struct Scene
+{
+    pub objects: Vec<SceneObject>,
+}
+
+impl Scene
+{
+    pub fn new() -> Self
+    {
+        Scene {
+            objects: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, object: SceneObject)
+    {
+        self.objects.push(object);
+    }
+
+    /// Builds one object per model loaded by `tobj`, all sharing the same transformation
+    ///
+    /// Each mesh gets the material it is assigned to in `materials`, or `default_material` if it has none.
+    pub fn from_models(models: Vec<tobj::Model>, materials: &Vec<Material>, transformation: &Mat4,
+                       default_material: &Material, normal_mode: NormalMode) -> Self
+    {
+        let mut scene = Scene::new();
+        for model in models.into_iter()
+        {
+            let material = model.mesh.material_id
+                .and_then(|id| materials.get(id))
+                .unwrap_or(default_material);
+            scene.add(SceneObject::new(model.name, MeshData::from(model.mesh), transformation.clone(), material.clone(),
+                                       normal_mode));
+        }
+        return scene;
+    }
+
+    pub fn set_normal_mode(&mut self, normal_mode: NormalMode)
+    {
+        for object in self.objects.iter_mut()
+        {
+            object.set_normal_mode(normal_mode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::data::{_Mat, Length};
+    use crate::shading::MetallicRoughness;
+    use crate::transformations::translate_obj;
+
+    use super::*;
+
+    fn material() -> Material
+    {
+        Material::new(Vec3::new(0.1), Vec3::new(0.5), Vec3::new(1.0), Vec3::new(0.5), 16.0)
+    }
+
+    #[test]
+    fn test_object_world_geometry_follows_transformation() {
+        let mesh = MeshData::new(vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0], vec![0, 1, 2]);
+        let mut object = SceneObject::new("triangle".to_string(), mesh, Mat4::identity(), material(), NormalMode::Auto);
+        assert_eq!(object.normals_wc().len(), 3);
+        assert_eq!(object.normals_wc()[0].vec.z(), 1.0);
+        object.set_transformation(translate_obj(&Mat4::identity(), &Vec3::new_xyz(0.0, 0.0, -2.0)));
+        assert_eq!(object.vertices_wc()[1].position.x(), 1.0);
+        assert_eq!(object.vertices_wc()[1].position.z(), -2.0);
+        assert_eq!(object.vertices_os()[1].position.z(), 0.0);
+    }
+
+    #[test]
+    fn test_authored_normals_and_normal_modes() {
+        // a tent whose authored normals all point the same way, unlike its faces
+        let mut mesh = MeshData::new(vec![-1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0],
+                                     vec![0, 2, 1, 2, 3, 1]);
+        mesh.normals = vec![1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0, 1.0, 0.0];
+        mesh.texcoords = vec![0.0, 0.0, 0.5, 1.0, 0.5, 0.0, 1.0, 0.0];
+        // stretching along x must tilt the normals towards y to keep them perpendicular to the surface
+        let mut transformation = Mat4::identity();
+        transformation._set_entry(0, 0, 3.0);
+        let mut object = SceneObject::new("tent".to_string(), mesh, transformation, material(), NormalMode::Auto);
+        assert!(object.has_authored_normals());
+        assert_eq!(object.texcoords().len(), 4);
+        let n = object.normals_wc()[2].vec;
+        assert!((n.y() / n.x() - 3.0).abs() < 1e-4);
+        assert!((Vec3::from(&n).get_length() - 1.0).abs() < 1e-5);
+
+        object.set_normal_mode(NormalMode::Smooth);
+        assert!(!object.has_authored_normals());
+        assert_eq!(object.vertices_wc().len(), 4);
+        assert_eq!(object.normals_wc().len(), 4);
+
+        object.set_normal_mode(NormalMode::Flat);
+        assert_eq!(object.vertices_wc().len(), 6);
+        assert_eq!(object.texcoords().len(), 6);
+        // both copies of the apex keep the normal of their own face, which lean to opposite sides
+        let (n1, n2) = (object.normals_wc()[2].vec, object.normals_wc()[5].vec);
+        assert!(n1.x() < 0.0 && n2.x() > 0.0);
+    }
+
+    #[test]
+    fn test_models_use_their_assigned_material() {
+        let positions = vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let model = |name: &str, material_id| {
+            let mut mesh = tobj::Mesh::default();
+            mesh.positions = positions.clone();
+            mesh.indices = vec![0, 1, 2];
+            mesh.material_id = material_id;
+            tobj::Model::new(mesh, name.to_string())
+        };
+        let models = vec![model("red", Some(0)), model("plain", None), model("broken", Some(7))];
+        let mut mtl = tobj::Material::default();
+        mtl.name = "red".to_string();
+        mtl.diffuse = [1.0, 0.0, 0.0];
+        mtl.shininess = 32.0;
+        mtl.dissolve = 0.5;
+        mtl.diffuse_texture = "red.png".to_string();
+        mtl.unknown_param.insert("Pm".to_string(), "1".to_string());
+        mtl.unknown_param.insert("Pr".to_string(), " 0.25".to_string());
+        let materials = vec![Material::from_mtl(&mtl, &material())];
+        let scene = Scene::from_models(models, &materials, &Mat4::identity(), &material(), NormalMode::Auto);
+
+        let red = &scene.objects[0].material;
+        assert_eq!(red.name, "red");
+        assert_eq!((red.diffuse.r(), red.diffuse.g()), (1.0, 0.0));
+        assert_eq!((red.specular, red.dissolve), (32.0, 0.5));
+        assert_eq!(red.diffuse_texture.as_deref(), Some("red.png"));
+        assert!(red.ambient_texture.is_none());
+        assert_eq!(red.global_reflection.r(), material().global_reflection.r());
+        assert_eq!(red.metallic_roughness, Some(MetallicRoughness { metallic: 1.0, roughness: 0.25 }));
+        assert!(material().metallic_roughness.is_none());
+        // meshes without a valid material id fall back to the default material
+        assert_eq!(scene.objects[1].material.diffuse.r(), 0.5);
+        assert_eq!(scene.objects[2].material.diffuse.r(), 0.5);
+    }
+}
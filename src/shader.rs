@@ -0,0 +1,493 @@
+//! Programmable vertex and fragment stages
+//!
+//! A vertex shader turns every vertex of an object into `Varyings`, which the rasterizer
+//! interpolates perspective-correctly for the fragment shader. Gouraud and Phong shading are
+//! built on these traits like any custom effect.
+
+use std::sync::Arc;
+
+use crate::data::{Add, Normalize, Product, ScalarMul, Vec2, Vec3, Vec4};
+use crate::err::{DimensionMismatchError, VaryingsFullError};
+use crate::shading::{Fragment, Light, lighting, LightingModel, Material};
+use crate::texture::Texture;
+
+/// Most varyings a vertex shader can hand to the fragment shader
+pub const MAX_VARYINGS: usize = 16;
+/// Most floats all varyings of a vertex shader can take together
+///
+/// The texture coordinates and their derivatives, which the pipeline adds, do not count.
+pub const MAX_VARYING_FLOATS: usize = 32;
+/// Varyings and floats kept for the texture coordinates and their derivatives
+const PIPELINE_VARYINGS: usize = 2;
+const PIPELINE_FLOATS: usize = 6;
+
+/// Type of a varying
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VaryingKind
+{
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl VaryingKind
+{
+    #[inline]
+    pub fn size(&self) -> usize
+    {
+        match self {
+            VaryingKind::Float => 1,
+            VaryingKind::Vec2 => 2,
+            VaryingKind::Vec3 => 3,
+            VaryingKind::Vec4 => 4,
+        }
+    }
+}
+
+/// Typed attributes handed from the vertex shader to the fragment shader, e.g. colors, texture
+/// coordinates, tangents or custom floats
+///
+/// Each `push_` appends a varying and returns its slot, which the fragment shader reads back
+/// with the getter of the same type, or an error past `MAX_VARYINGS` or `MAX_VARYING_FLOATS`.
+/// Getters panic when the slot holds another type. Varyings are stored as consecutive floats, so
+/// they are blended all at once. The pipeline appends the texture coordinates of the mesh and
+/// their derivatives after the outputs of the vertex shader, in room of their own.
+#[derive(Debug, Copy, Clone)]
+pub struct Varyings
+{
+    floats: [f32; MAX_VARYING_FLOATS + PIPELINE_FLOATS],
+    kinds: [VaryingKind; MAX_VARYINGS + PIPELINE_VARYINGS],
+    /// index of the first float of each varying
+    offsets: [u8; MAX_VARYINGS + PIPELINE_VARYINGS],
+    len: usize,
+    floats_len: usize,
+    /// slot of the texture coordinates, which the rasterizer differentiates across 2x2 quads
+    texcoord: Option<u8>,
+    /// slot of the changes of the texture coordinates to the next pixel on the right and above
+    texcoord_derivatives: Option<u8>,
+}
+
+impl Varyings
+{
+    pub fn new() -> Self
+    {
+        Varyings {
+            floats: [0.0; MAX_VARYING_FLOATS + PIPELINE_FLOATS],
+            kinds: [VaryingKind::Float; MAX_VARYINGS + PIPELINE_VARYINGS],
+            offsets: [0; MAX_VARYINGS + PIPELINE_VARYINGS],
+            len: 0,
+            floats_len: 0,
+            texcoord: None,
+            texcoord_derivatives: None,
+        }
+    }
+
+    /// Number of varyings
+    #[inline]
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    #[inline]
+    pub fn kind(&self, slot: usize) -> VaryingKind
+    {
+        assert!(slot < self.len, "there is no varying in slot {} of {}", slot, self.len);
+        self.kinds[slot]
+    }
+
+    pub fn push_float(&mut self, value: f32) -> Result<usize, VaryingsFullError>
+    {
+        self.push(VaryingKind::Float, &[value])
+    }
+
+    pub fn push_vec2(&mut self, value: &Vec2) -> Result<usize, VaryingsFullError>
+    {
+        self.push(VaryingKind::Vec2, &[value.x(), value.y()])
+    }
+
+    pub fn push_vec3(&mut self, value: &Vec3) -> Result<usize, VaryingsFullError>
+    {
+        self.push(VaryingKind::Vec3, &[value.x(), value.y(), value.z()])
+    }
+
+    pub fn push_vec4(&mut self, value: &Vec4) -> Result<usize, VaryingsFullError>
+    {
+        self.push(VaryingKind::Vec4, &[value.x(), value.y(), value.z(), value.w()])
+    }
+
+    /// Pushes the texture coordinates, there is at most one slot of them
+    pub fn push_texcoord(&mut self, uv: &Vec2) -> usize
+    {
+        assert!(self.texcoord.is_none(), "the varyings hold texture coordinates already");
+        let slot = self.push_unchecked(VaryingKind::Vec2, &[uv.x(), uv.y()]);
+        self.texcoord = Some(slot as u8);
+        return slot;
+    }
+
+    /// Pushes the changes of the texture coordinates to the next pixel on the right and above
+    pub fn push_texcoord_derivatives(&mut self, duv_dx: &Vec2, duv_dy: &Vec2) -> usize
+    {
+        assert!(self.texcoord_derivatives.is_none(), "the varyings hold texture derivatives already");
+        let slot = self.push_unchecked(VaryingKind::Vec4, &[duv_dx.u(), duv_dx.v(), duv_dy.u(), duv_dy.v()]);
+        self.texcoord_derivatives = Some(slot as u8);
+        return slot;
+    }
+
+    /// Slot of the texture coordinates, `None` for meshes without them
+    #[inline]
+    pub fn texcoord_slot(&self) -> Option<usize>
+    {
+        self.texcoord.map(|slot| slot as usize)
+    }
+
+    /// Changes of the texture coordinates to the next pixel on the right and above, `None` until
+    /// the rasterizer has computed them
+    #[inline]
+    pub fn texcoord_derivatives(&self) -> Option<(Vec2, Vec2)>
+    {
+        let d = self.vec4(self.texcoord_derivatives? as usize);
+        return Some((Vec2::new_uv(d.x(), d.y()), Vec2::new_uv(d.z(), d.w())));
+    }
+
+    #[inline]
+    pub fn float(&self, slot: usize) -> f32
+    {
+        self.floats_of(slot, VaryingKind::Float)[0]
+    }
+
+    #[inline]
+    pub fn vec2(&self, slot: usize) -> Vec2
+    {
+        let v = self.floats_of(slot, VaryingKind::Vec2);
+        Vec2::new_xy(v[0], v[1])
+    }
+
+    #[inline]
+    pub fn vec3(&self, slot: usize) -> Vec3
+    {
+        let v = self.floats_of(slot, VaryingKind::Vec3);
+        Vec3::new_xyz(v[0], v[1], v[2])
+    }
+
+    #[inline]
+    pub fn vec4(&self, slot: usize) -> Vec4
+    {
+        let v = self.floats_of(slot, VaryingKind::Vec4);
+        Vec4::new_xyzw(v[0], v[1], v[2], v[3])
+    }
+
+    /// Whether both hold varyings of the same types in the same order
+    pub fn same_layout(&self, other: &Varyings) -> bool
+    {
+        self.len == other.len && self.kinds[..self.len] == other.kinds[..other.len] && self.texcoord == other.texcoord
+            && self.texcoord_derivatives == other.texcoord_derivatives
+    }
+
+    /// Pushes a varying of the vertex shader, within the room it has left
+    fn push(&mut self, kind: VaryingKind, values: &[f32]) -> Result<usize, VaryingsFullError>
+    {
+        let (pipeline_len, pipeline_floats) = [(self.texcoord, 2), (self.texcoord_derivatives, 4)].iter()
+            .filter(|(slot, _)| slot.is_some())
+            .fold((0, 0), |(len, floats), (_, size)| (len + 1, floats + size));
+        if self.len - pipeline_len >= MAX_VARYINGS || self.floats_len - pipeline_floats + values.len() > MAX_VARYING_FLOATS
+        {
+            return Err(VaryingsFullError::new(MAX_VARYINGS, MAX_VARYING_FLOATS));
+        }
+        return Ok(self.push_unchecked(kind, values));
+    }
+
+    /// The pipeline has room of its own and pushes each of its varyings at most once
+    fn push_unchecked(&mut self, kind: VaryingKind, values: &[f32]) -> usize
+    {
+        let slot = self.len;
+        self.kinds[slot] = kind;
+        self.offsets[slot] = self.floats_len as u8;
+        self.floats[self.floats_len..self.floats_len + values.len()].copy_from_slice(values);
+        self.len += 1;
+        self.floats_len += values.len();
+        return slot;
+    }
+
+    #[inline]
+    fn floats_of(&self, slot: usize, kind: VaryingKind) -> &[f32]
+    {
+        let actual = self.kind(slot);
+        assert_eq!(actual, kind, "varying {} is a {:?}, not a {:?}", slot, actual, kind);
+        let offset = self.offsets[slot] as usize;
+        &self.floats[offset..offset + kind.size()]
+    }
+}
+
+impl Default for Varyings
+{
+    fn default() -> Self
+    {
+        Varyings::new()
+    }
+}
+
+impl ScalarMul for Varyings
+{
+    fn scalar_mul(&self, s: f32) -> Self
+    {
+        let mut varyings = *self;
+        varyings.scalar_mul_(s);
+        return varyings;
+    }
+
+    fn scalar_mul_(&mut self, s: f32)
+    {
+        self.floats[..self.floats_len].iter_mut().for_each(|v| *v *= s);
+    }
+}
+
+impl Add for Varyings
+{
+    fn add(&self, other: &Self) -> Result<Self, DimensionMismatchError>
+    {
+        if !self.same_layout(other)
+        {
+            return Err(DimensionMismatchError::new([self.floats_len, 1], [other.floats_len, 1]));
+        }
+        return Ok(self._add(other));
+    }
+
+    /// Vertices of one triangle come from the same shader, so their layouts match
+    fn add_(&mut self, other: &Self)
+    {
+        debug_assert!(self.same_layout(other));
+        for (v, o) in self.floats[..self.floats_len].iter_mut().zip(other.floats.iter())
+        {
+            *v += *o;
+        }
+    }
+
+    fn _add(&self, other: &Self) -> Self
+    {
+        let mut varyings = *self;
+        varyings.add_(other);
+        return varyings;
+    }
+}
+
+/// The built-in shaders
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShadingMode
+{
+    Flat,
+    Gouraud,
+    Phong,
+}
+
+impl ShadingMode
+{
+    pub fn shader(&self) -> Arc<dyn Shader>
+    {
+        match self {
+            ShadingMode::Flat => Arc::new(FlatShader),
+            ShadingMode::Gouraud => Arc::new(GouraudShader),
+            ShadingMode::Phong => Arc::new(PhongShader),
+        }
+    }
+}
+
+/// What shaders read that is the same for a whole object
+pub struct Uniforms<'a>
+{
+    /// lights in eye coordinates
+    pub lights: &'a [Light],
+    /// light reaching every point from all directions, scaled by the ambient color of the material
+    pub ambient_light: Vec3,
+    pub lighting: LightingModel,
+    pub material: &'a Material,
+    pub diffuse_texture: Option<&'a Texture>,
+}
+
+pub trait VertexShader: Send + Sync
+{
+    /// Varyings of a vertex from its position and normalized normal in eye coordinates
+    fn shade_vertex(&self, position_ec: &Vec4, normal_ec: &Vec3, uniforms: &Uniforms) -> Varyings;
+
+    /// Whether to run once per triangle instead, at its centroid with the normal of its front face,
+    /// which gives the same varyings to its whole surface
+    fn per_face(&self) -> bool
+    {
+        false
+    }
+}
+
+pub trait FragmentShader: Send + Sync
+{
+    /// Color of a fragment, whose varyings are interpolated from the corners of its triangle
+    fn shade_fragment(&self, fragment: &Fragment, uniforms: &Uniforms) -> Vec3;
+}
+
+/// A vertex and a fragment shader that work together, implemented by any type that is both
+pub trait Shader: VertexShader + FragmentShader {}
+
+impl<T: VertexShader + FragmentShader> Shader for T {}
+
+/// Texture color under the fragment, white without a texture
+///
+/// Meshes without texture coordinates take the texel at the origin.
+fn diffuse_texel(fragment: &Fragment, uniforms: &Uniforms) -> Vec3
+{
+    let texture = match uniforms.diffuse_texture {
+        Some(texture) => texture,
+        None => return Vec3::new(1.0)
+    };
+    let zero = Vec2::new(0.0);
+    let (uv, duv_dx, duv_dy) = fragment.texcoord().unwrap_or((zero, zero, zero));
+    return texture.sample_grad(&uv, &duv_dx, &duv_dy);
+}
+
+/// Lights `position_ec` with `normal_ec` of unit length, seen from the eye at the origin
+fn light_point(position_ec: &Vec3, normal_ec: &Vec3, diffuse: &Vec3, uniforms: &Uniforms) -> Vec3
+{
+    let mut view_dir = position_ec.scalar_mul(-1.0);
+    view_dir.normalize_();
+    return lighting(uniforms.lighting, position_ec, normal_ec, &view_dir, diffuse, uniforms.material, uniforms.lights,
+                    &uniforms.ambient_light);
+}
+
+/// Lights the vertices and interpolates their colors
+///
+/// Vertices are lit with the untextured material, so the texture also tints the ambient and
+/// specular terms.
+pub struct GouraudShader;
+
+impl VertexShader for GouraudShader
+{
+    fn shade_vertex(&self, position_ec: &Vec4, normal_ec: &Vec3, uniforms: &Uniforms) -> Varyings
+    {
+        let mut varyings = Varyings::new();
+        varyings.push_vec3(&light_point(&Vec3::from(position_ec), normal_ec, &uniforms.material.diffuse, uniforms)).unwrap();
+        return varyings;
+    }
+}
+
+impl FragmentShader for GouraudShader
+{
+    fn shade_fragment(&self, fragment: &Fragment, uniforms: &Uniforms) -> Vec3
+    {
+        fragment.varyings.vec3(0).product(&diffuse_texel(fragment, uniforms))
+    }
+}
+
+/// Lights every triangle once with the normal of its plane, which shows the facets of a mesh
+pub struct FlatShader;
+
+impl VertexShader for FlatShader
+{
+    fn shade_vertex(&self, position_ec: &Vec4, normal_ec: &Vec3, uniforms: &Uniforms) -> Varyings
+    {
+        GouraudShader.shade_vertex(position_ec, normal_ec, uniforms)
+    }
+
+    fn per_face(&self) -> bool
+    {
+        true
+    }
+}
+
+impl FragmentShader for FlatShader
+{
+    fn shade_fragment(&self, fragment: &Fragment, uniforms: &Uniforms) -> Vec3
+    {
+        GouraudShader.shade_fragment(fragment, uniforms)
+    }
+}
+
+/// Lights every fragment with the interpolated normal, the texture color is multiplied with the
+/// diffuse color like MTL `map_Kd`
+pub struct PhongShader;
+
+impl PhongShader
+{
+    const POSITION: usize = 0;
+    const NORMAL: usize = 1;
+}
+
+impl VertexShader for PhongShader
+{
+    fn shade_vertex(&self, position_ec: &Vec4, normal_ec: &Vec3, _uniforms: &Uniforms) -> Varyings
+    {
+        let mut varyings = Varyings::new();
+        varyings.push_vec3(&Vec3::from(position_ec)).unwrap();
+        varyings.push_vec3(normal_ec).unwrap();
+        return varyings;
+    }
+}
+
+impl FragmentShader for PhongShader
+{
+    fn shade_fragment(&self, fragment: &Fragment, uniforms: &Uniforms) -> Vec3
+    {
+        let position_ec = fragment.varyings.vec3(Self::POSITION);
+        let mut normal_ec = fragment.varyings.vec3(Self::NORMAL);
+        normal_ec.normalize_();
+        let diffuse = uniforms.material.diffuse.product(&diffuse_texel(fragment, uniforms));
+        return light_point(&position_ec, &normal_ec, &diffuse, uniforms);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_varyings_interpolate() {
+        let mut a = Varyings::new();
+        assert_eq!(a.push_float(1.0).unwrap(), 0);
+        assert_eq!(a.push_vec3(&Vec3::new_xyz(1.0, 2.0, 3.0)).unwrap(), 1);
+        a.push_vec2(&Vec2::new_uv(0.0, 1.0)).unwrap();
+        let mut b = Varyings::new();
+        b.push_float(3.0).unwrap();
+        b.push_vec3(&Vec3::new_xyz(3.0, 4.0, 5.0)).unwrap();
+        b.push_vec2(&Vec2::new_uv(1.0, 0.0)).unwrap();
+        let mid = a.scalar_mul(0.5)._add(&b.scalar_mul(0.5));
+        assert_eq!(mid.len(), 3);
+        assert_eq!(mid.float(0), 2.0);
+        assert_eq!(mid.vec3(1).z(), 4.0);
+        assert_eq!((mid.vec2(2).u(), mid.vec2(2).v()), (0.5, 0.5));
+        assert_eq!(mid.kind(2), VaryingKind::Vec2);
+        // varyings of other types do not blend
+        let mut c = Varyings::new();
+        c.push_vec4(&Vec4::new(1.0)).unwrap();
+        c.push_float(0.0).unwrap();
+        assert!(a.add(&c).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_varyings_are_typed() {
+        let mut varyings = Varyings::new();
+        varyings.push_vec2(&Vec2::new(0.0)).unwrap();
+        varyings.vec3(0);
+    }
+
+    #[test]
+    fn test_varyings_are_bounded() {
+        let mut varyings = Varyings::new();
+        // the texture coordinates and their derivatives take none of the room of the shader
+        varyings.push_texcoord(&Vec2::new_uv(0.25, 0.75));
+        for _ in 0..MAX_VARYING_FLOATS / 4
+        {
+            varyings.push_vec4(&Vec4::new(0.0)).unwrap();
+        }
+        assert!(varyings.push_float(0.0).is_err());
+        let slot = varyings.push_texcoord_derivatives(&Vec2::new_uv(1.0, 0.0), &Vec2::new_uv(0.0, 2.0));
+        assert_eq!(slot, MAX_VARYING_FLOATS / 4 + 1);
+        assert_eq!(varyings.vec2(varyings.texcoord_slot().unwrap()).v(), 0.75);
+        assert_eq!(varyings.texcoord_derivatives().unwrap().1.v(), 2.0);
+
+        let mut varyings = Varyings::new();
+        for _ in 0..MAX_VARYINGS
+        {
+            varyings.push_float(0.0).unwrap();
+        }
+        assert!(varyings.push_float(0.0).is_err());
+    }
+}
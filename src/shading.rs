@@ -1,6 +1,10 @@
 use rayon::prelude::*;
 
-use crate::data::{Add, Mat4, MatVecDot, Minus, Product, ScalarMul, Vec3, Vec4, VecDot, ScalarDiv};
+use crate::data::{Add, Cross, Length, Mat4, MatVecDot, Minus, Normalize, Product, ScalarDiv, ScalarMul, Vec2, Vec3, Vec4, VecDot};
+use crate::buffer::PolygonOffset;
+use crate::clipping::{ClipVertex, clip_triangle, DepthRange, outcode};
+use crate::shader::Varyings;
+use crate::simd::quad_edge_values;
 use crate::transformations::{inverse_look_at, look_at};
 
 pub struct Camera
@@ -66,25 +70,97 @@ pub struct Triangle<'a>
     v1: &'a Vertex,
     v2: &'a Vertex,
     v3: &'a Vertex,
-    n1: &'a Normal,
-    n2: &'a Normal,
-    n3: &'a Normal,
+    varyings: [Varyings; 3],
+    /// corners in clip space and their outcodes, once `project` has run
+    clip: Option<([Vec4; 3], [u8; 3])>,
 }
 
 impl<'a> Triangle<'a>
 {
-    pub fn new(vn1: (&'a Vertex, &'a Normal), vn2: (&'a Vertex, &'a Normal), vn3: (&'a Vertex, &'a Normal)) -> Self
+    pub fn new(v1: &'a Vertex, v2: &'a Vertex, v3: &'a Vertex) -> Self
     {
         Triangle
         {
-            v1: vn1.0,
-            n1: vn1.1,
-            v2: vn2.0,
-            n2: vn2.1,
-            v3: vn3.0,
-            n3: vn3.1,
+            v1,
+            v2,
+            v3,
+            varyings: [Varyings::new(); 3],
+            clip: None,
         }
     }
+
+    #[inline]
+    pub fn positions(&self) -> [&Vec4; 3]
+    {
+        [&self.v1.position, &self.v2.position, &self.v3.position]
+    }
+
+    /// Normal of the side the corners run counterclockwise around, twice as long as the area
+    pub fn ccw_normal(&self) -> Vec3
+    {
+        let (p1, p2, p3) = (Vec3::from(&self.v1.position), Vec3::from(&self.v2.position), Vec3::from(&self.v3.position));
+        return p2._minus(&p1).cross(&p3._minus(&p1));
+    }
+
+    pub fn centroid(&self) -> Vec4
+    {
+        self.v1.position._add(&self.v2.position)._add(&self.v3.position).scalar_mul(1.0 / 3.0)
+    }
+
+    /// Sets the outputs of the vertex shader for the three vertices, which default to none
+    pub fn with_varyings(mut self, f1: Varyings, f2: Varyings, f3: Varyings) -> Self
+    {
+        self.varyings = [f1, f2, f3];
+        return self;
+    }
+
+    /// Appends the texture coordinates of the three vertices to their varyings
+    pub fn with_texcoords(mut self, t1: Vec2, t2: Vec2, t3: Vec2) -> Self
+    {
+        for (varyings, uv) in self.varyings.iter_mut().zip([t1, t2, t3].iter())
+        {
+            varyings.push_texcoord(uv);
+        }
+        return self;
+    }
+
+    /// Gives the outputs of a vertex shader run once for the whole face to the three vertices,
+    /// which keep their texture coordinates
+    pub fn with_face_varyings(mut self, face: Varyings) -> Self
+    {
+        for varyings in self.varyings.iter_mut()
+        {
+            let mut shaded = face;
+            if let Some(slot) = varyings.texcoord_slot()
+            {
+                shaded.push_texcoord(&varyings.vec2(slot));
+            }
+            *varyings = shaded;
+        }
+        return self;
+    }
+
+    /// Keeps the corners in clip space and their outcodes, so clipping does not project them again
+    pub fn project(mut self, perspective_mat: &Mat4, depth_range: DepthRange) -> Self
+    {
+        self.clip = Some(self.project_corners(perspective_mat, depth_range));
+        return self;
+    }
+
+    /// Outcodes of the corners, `None` until `project` has run
+    pub fn outcodes(&self) -> Option<[u8; 3]>
+    {
+        self.clip.map(|(_, codes)| codes)
+    }
+
+    fn project_corners(&self, perspective_mat: &Mat4, depth_range: DepthRange) -> ([Vec4; 3], [u8; 3])
+    {
+        let positions_cc = [perspective_mat.mat_vec_dot(&self.v1.position), perspective_mat.mat_vec_dot(&self.v2.position),
+            perspective_mat.mat_vec_dot(&self.v3.position)];
+        let codes = [outcode(&positions_cc[0], depth_range), outcode(&positions_cc[1], depth_range),
+            outcode(&positions_cc[2], depth_range)];
+        return (positions_cc, codes);
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -93,40 +169,284 @@ pub struct Fragment
     pub x: u32,
     pub y: u32,
     pub z: f32,
-    pub normal_ec: Vec4,
-    pub coord_ec: Vec4,
+    /// outputs of the vertex shader, interpolated perspective-correctly
+    pub varyings: Varyings,
 }
 
+impl Fragment
+{
+    /// Texture coordinates and their change to the next pixel on the right and above, `None` for
+    /// meshes without texture coordinates
+    ///
+    /// The changes are shared by the 2x2 quad of the fragment.
+    pub fn texcoord(&self) -> Option<(Vec2, Vec2, Vec2)>
+    {
+        let slot = self.varyings.texcoord_slot()?;
+        let (duv_dx, duv_dy) = self.varyings.texcoord_derivatives()?;
+        return Some((self.varyings.vec2(slot), duv_dx, duv_dy));
+    }
+}
 
-#[derive(Copy, Clone)]
+
+#[derive(Clone, Debug)]
 pub struct Material {
+    pub name: String,
     pub ambient: Vec3,
     pub diffuse: Vec3,
     pub reflection: Vec3,
     pub global_reflection: Vec3,
     pub specular: f32,
+    /// Opacity, 1.0 is fully opaque
+    pub dissolve: f32,
+    /// Texture file names as written in the MTL file, relative to it
+    pub ambient_texture: Option<String>,
+    pub diffuse_texture: Option<String>,
+    pub specular_texture: Option<String>,
+    pub normal_texture: Option<String>,
+    /// Parameters of `LightingModel::Pbr`, approximated from the Phong ones when missing
+    pub metallic_roughness: Option<MetallicRoughness>,
 }
 
+/// Physically based material in the metallic-roughness workflow, whose base color is the diffuse color
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MetallicRoughness
+{
+    /// 0 for dielectrics up to 1 for metals
+    pub metallic: f32,
+    /// perceptual roughness, from 0 for a mirror up to 1
+    pub roughness: f32,
+}
+
+impl Material {
+    /// An opaque material without textures
+    pub fn new(ambient: Vec3, diffuse: Vec3, reflection: Vec3, global_reflection: Vec3, specular: f32) -> Self {
+        Material {
+            name: String::new(),
+            ambient,
+            diffuse,
+            reflection,
+            global_reflection,
+            specular,
+            dissolve: 1.0,
+            ambient_texture: None,
+            diffuse_texture: None,
+            specular_texture: None,
+            normal_texture: None,
+            metallic_roughness: None,
+        }
+    }
 
-#[derive(Copy, Clone)]
-pub struct Light {
+    /// Turns the material into a physically based one
+    pub fn with_metallic_roughness(mut self, metallic: f32, roughness: f32) -> Self
+    {
+        self.metallic_roughness = Some(MetallicRoughness { metallic, roughness });
+        return self;
+    }
+
+    /// Parameters for `LightingModel::Pbr`, a non-metal with the roughness matching the specular
+    /// exponent if the material has none
+    pub fn metallic_roughness(&self) -> MetallicRoughness
+    {
+        self.metallic_roughness.unwrap_or_else(|| MetallicRoughness {
+            metallic: 0.0,
+            // the GGX roughness whose highlight is about as wide as that of the Phong exponent
+            roughness: (2.0 / (self.specular + 2.0)).sqrt().sqrt(),
+        })
+    }
+
+    /// Maps Ka, Kd, Ks, Ns, d, the texture maps of an MTL material and the Pm and Pr of its PBR extension
+    ///
+    /// MTL has no global reflection, so it is taken from `fallback`.
+    pub fn from_mtl(mtl: &tobj::Material, fallback: &Material) -> Self {
+        let texture = |name: &String| if name.is_empty() { None } else { Some(name.clone()) };
+        let param = |key: &str| mtl.unknown_param.get(key).and_then(|v| v.trim().parse::<f32>().ok())
+            .map(|v| v.max(0.0).min(1.0));
+        let metallic_roughness = match (param("Pm"), param("Pr")) {
+            (None, None) => None,
+            (metallic, roughness) => Some(MetallicRoughness {
+                metallic: metallic.unwrap_or(0.0),
+                roughness: roughness.unwrap_or(1.0),
+            }),
+        };
+        Material {
+            name: mtl.name.clone(),
+            ambient: Vec3::new_rgb(mtl.ambient[0], mtl.ambient[1], mtl.ambient[2]),
+            diffuse: Vec3::new_rgb(mtl.diffuse[0], mtl.diffuse[1], mtl.diffuse[2]),
+            reflection: Vec3::new_rgb(mtl.specular[0], mtl.specular[1], mtl.specular[2]),
+            global_reflection: fallback.global_reflection,
+            specular: mtl.shininess,
+            dissolve: mtl.dissolve,
+            ambient_texture: texture(&mtl.ambient_texture),
+            diffuse_texture: texture(&mtl.diffuse_texture),
+            specular_texture: texture(&mtl.specular_texture),
+            normal_texture: texture(&mtl.normal_texture),
+            metallic_roughness,
+        }
+    }
+}
+
+
+/// Shape of the light a `Light` sends out
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightKind
+{
+    /// Shines in every direction from its position
+    Point,
+    /// Shines along its direction from infinitely far away, like the sun
+    Directional,
+    /// Shines from its position into a cone around its direction, fading out from the inner to the
+    /// outer half angle in radians
+    Spot { inner_angle: f32, outer_angle: f32 },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Light
+{
+    pub kind: LightKind,
+    /// unused by directional lights
     pub position: Vec3,
-    pub original_position: Vec3,
-    pub ambient: Vec3,
-    pub diffuse: Vec3,
+    /// where the light goes of unit length, unused by point lights
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    /// constant, linear and quadratic factors of the distance in the divisor of point and spot lights
+    pub attenuation: Vec3,
+}
+
+impl Light
+{
+    /// A white point light that does not fade with the distance
+    pub fn point(position: Vec3) -> Self
+    {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: Vec3::new_xyz(0.0, 0.0, -1.0),
+            color: Vec3::new(1.0),
+            intensity: 1.0,
+            attenuation: Vec3::new_xyz(1.0, 0.0, 0.0),
+        }
+    }
+
+    /// A white light shining along `direction`
+    pub fn directional(direction: Vec3) -> Self
+    {
+        Light {
+            kind: LightKind::Directional,
+            position: Vec3::new(0.0),
+            direction: direction.normalize(),
+            ..Light::point(Vec3::new(0.0))
+        }
+    }
+
+    /// A white spot light at `position` shining along `direction`, with half angles in radians
+    pub fn spot(position: Vec3, direction: Vec3, inner_angle: f32, outer_angle: f32) -> Self
+    {
+        Light {
+            kind: LightKind::Spot { inner_angle, outer_angle },
+            direction: direction.normalize(),
+            ..Light::point(position)
+        }
+    }
+
+    /// The light moved by the rigid transformation `mat`, e.g. into eye coordinates
+    pub fn transformed(&self, mat: &Mat4) -> Self
+    {
+        let mut position = mat.mat_vec_dot(&Vec4::from(&self.position, 1.0));
+        position.scalar_div_(position.w());
+        let mut direction = Vec3::from(&mat.mat_vec_dot(&Vec4::from(&self.direction, 0.0)));
+        direction.normalize_();
+        Light {
+            position: Vec3::from(&position),
+            direction,
+            ..*self
+        }
+    }
+
+    /// Unit direction from `point` to the light and the color of the light arriving at `point`
+    pub fn incident(&self, point: &Vec3) -> (Vec3, Vec3)
+    {
+        if self.kind == LightKind::Directional
+        {
+            return (self.direction.scalar_mul(-1.0), self.color.scalar_mul(self.intensity));
+        }
+        let to_light = self.position._minus(point);
+        let distance = to_light.get_length();
+        let light_direction = to_light.scalar_div(distance);
+        let attenuation = self.attenuation.x() + self.attenuation.y() * distance
+            + self.attenuation.z() * distance * distance;
+        let mut strength = self.intensity / attenuation;
+        if let LightKind::Spot { inner_angle, outer_angle } = self.kind
+        {
+            let cos_angle = -light_direction.dot(&self.direction);
+            let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+            let t = match cos_inner - cos_outer > f32::EPSILON {
+                true => ((cos_angle - cos_outer) / (cos_inner - cos_outer)).max(0.0).min(1.0),
+                false => if cos_angle >= cos_outer { 1.0 } else { 0.0 }
+            };
+            // smoothstep, so the edge of the cone is soft
+            strength *= t * t * (3.0 - 2.0 * t);
+        }
+        return (light_direction, self.color.scalar_mul(strength));
+    }
 }
 
 pub fn reflect(incident_vec: &Vec3, normalized_normal: &Vec3) -> Vec3 {
     incident_vec._minus(&normalized_normal.scalar_mul(2.0 * normalized_normal.dot(incident_vec)))
 }
 
-pub fn phong_lighting(
-    light_direction: &Vec3,
+/// How light reflects off surfaces
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightingModel
+{
+    /// Specular highlights from the mirrored light direction and the specular exponent
+    Phong,
+    /// Specular highlights from the half vector, wider than those of Phong for the same exponent
+    BlinnPhong,
+    /// Cook-Torrance with the GGX distribution, Smith geometry and Schlick Fresnel terms, driven by
+    /// the metallic-roughness parameters of the material
+    Pbr,
+}
+
+/// Reflectance at normal incidence of dielectrics
+const DIELECTRIC_F0: f32 = 0.04;
+
+/// Lighting of `position` by every light plus `ambient_light`, all in the same space
+///
+/// `diffuse` overrides the diffuse color of `material`, e.g. with a texture sample, and is the
+/// base color of physically based materials.
+pub fn lighting(
+    model: LightingModel,
+    position: &Vec3,
     normalized_normal: &Vec3,
     view_direction: &Vec3,
+    diffuse: &Vec3,
     material: &Material,
-    light: &Light,
+    lights: &[Light],
+    ambient_light: &Vec3,
 ) -> Vec3 {
+    let mut result = match model {
+        LightingModel::Pbr => ambient_light.product(diffuse),
+        _ => ambient_light.product(&material.ambient),
+    };
+    for light in lights.iter()
+    {
+        let (light_direction, light_color) = light.incident(position);
+        let reflected = match model {
+            LightingModel::Phong => phong(&light_direction, normalized_normal, view_direction, diffuse, material),
+            LightingModel::BlinnPhong => blinn_phong(&light_direction, normalized_normal, view_direction, diffuse, material),
+            LightingModel::Pbr => cook_torrance(&light_direction, normalized_normal, view_direction, diffuse,
+                                                &material.metallic_roughness()),
+        };
+        result.add_(&reflected.product(&light_color));
+    }
+    return result;
+}
+
+/// Light reflected towards `view_direction` from white light coming from `light_direction`
+fn phong(light_direction: &Vec3, normalized_normal: &Vec3, view_direction: &Vec3, diffuse: &Vec3,
+         material: &Material) -> Vec3
+{
     let reflected_light = reflect(&light_direction.scalar_mul(-1.), normalized_normal);
     let n_dot_l = f32::max(0.0, normalized_normal.dot(light_direction));
     let r_dot_l = f32::max(0.0, reflected_light.dot(view_direction));
@@ -135,29 +455,95 @@ pub fn phong_lighting(
     } else {
         r_dot_l.powf(material.specular)
     };
-    let ambient = light.ambient.product(&material.ambient);
-    let mut result = material.diffuse.scalar_mul(n_dot_l);
-    result.add_(&material.reflection.scalar_mul(r_dot_v_pow_n));
-    result.product_(&light.diffuse);
-    result.add_(&ambient);
-    return result;
+    let mut reflected = diffuse.scalar_mul(n_dot_l);
+    reflected.add_(&material.reflection.scalar_mul(r_dot_v_pow_n));
+    return reflected;
 }
 
-fn get_min_max(a: f32, b: f32, c: f32, upper_bound: f32, lower_bound: f32) -> (u32, u32)
+fn blinn_phong(light_direction: &Vec3, normalized_normal: &Vec3, view_direction: &Vec3, diffuse: &Vec3,
+               material: &Material) -> Vec3
 {
-    let mut min = f32::round(f32::min(f32::min(a, b), c));
-    let mut max = f32::round(f32::max(f32::max(a, b), c));
-    if min < lower_bound
+    let n_dot_l = normalized_normal.dot(light_direction);
+    if n_dot_l <= 0.0
     {
-        min = lower_bound;
+        return Vec3::new(0.0);
     }
-    if max > upper_bound
+    let half = light_direction._add(view_direction).normalize();
+    let n_dot_h = f32::max(0.0, normalized_normal.dot(&half));
+    let mut reflected = diffuse.scalar_mul(n_dot_l);
+    reflected.add_(&material.reflection.scalar_mul(n_dot_h.powf(material.specular)));
+    return reflected;
+}
+
+/// Lambertian diffuse plus the Cook-Torrance specular term
+///
+/// Both are scaled by pi, so that a white light on a white matte surface is as bright as with Phong.
+fn cook_torrance(light_direction: &Vec3, normalized_normal: &Vec3, view_direction: &Vec3, base_color: &Vec3,
+                 material: &MetallicRoughness) -> Vec3
+{
+    let n_dot_l = normalized_normal.dot(light_direction);
+    let n_dot_v = normalized_normal.dot(view_direction);
+    if n_dot_l <= 0.0 || n_dot_v <= 0.0
     {
-        max = upper_bound;
+        return Vec3::new(0.0);
     }
-    return (min as u32, max as u32);
+    let half = light_direction._add(view_direction).normalize();
+    let n_dot_h = f32::max(0.0, normalized_normal.dot(&half));
+    let v_dot_h = f32::max(0.0, view_direction.dot(&half));
+    // a perfectly smooth surface would reflect a point light into a single direction
+    let roughness = material.roughness.max(0.03);
+    let metallic = material.metallic;
+
+    // GGX normal distribution
+    let alpha_2 = roughness.powi(4);
+    let denominator = n_dot_h * n_dot_h * (alpha_2 - 1.0) + 1.0;
+    let distribution = alpha_2 / (std::f32::consts::PI * denominator * denominator);
+    // Smith geometry with the Schlick-GGX approximation for direct light
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = n_dot_l / (n_dot_l * (1.0 - k) + k) * n_dot_v / (n_dot_v * (1.0 - k) + k);
+    // Schlick Fresnel, metals tint their reflection with the base color
+    let f0 = Vec3::new(DIELECTRIC_F0).scalar_mul(1.0 - metallic)._add(&base_color.scalar_mul(metallic));
+    let fresnel = f0._add(&Vec3::new(1.0)._minus(&f0).scalar_mul((1.0 - v_dot_h).powi(5)));
+
+    let specular = fresnel.scalar_mul(distribution * geometry * std::f32::consts::PI / (4.0 * n_dot_l * n_dot_v));
+    // light that is not reflected enters the surface, metals absorb it
+    let diffuse = Vec3::new(1.0)._minus(&fresnel).product(base_color).scalar_mul(1.0 - metallic);
+    return diffuse._add(&specular).scalar_mul(n_dot_l);
+}
+
+/// Converts a float color to opaque RGBA8
+#[inline]
+pub fn to_color(mut color: Vec3) -> [u8; 4] {
+    clamp_(&mut color);
+    color.scalar_mul_(255.);
+    let x = color.r().round();
+    let y = color.g().round();
+    let z = color.b().round();
+    [x as u8, y as u8, z as u8, 255]
 }
 
+#[inline]
+fn clamp_(color: &mut Vec3) {
+    color.set_r(clamp_float(color.r()));
+    color.set_g(clamp_float(color.g()));
+    color.set_b(clamp_float(color.b()));
+}
+
+#[inline]
+fn clamp_float(x: f32) -> f32 {
+    if x < 0. {
+        return 0.;
+    }
+    if x > 1. {
+        return 1.;
+    }
+    x
+}
+
+/// Sums the attributes weighted by `w` and scales the sum by `z`
+///
+/// For perspective correctness, `w` are the barycentric weights divided by the clip space w of
+/// each vertex and `z` is the reciprocal of their sum.
 fn interpolate<T>(w: (f32, f32, f32), v: (&T, &T, &T), z: f32) -> T where T: ScalarMul + Add
 {
     let mut interpolated = v.0.scalar_mul(w.0);
@@ -167,119 +553,405 @@ fn interpolate<T>(w: (f32, f32, f32), v: (&T, &T, &T), z: f32) -> T where T: Sca
     return interpolated;
 }
 
-pub fn rasterization(triangles_ec: &Vec<Triangle>, perspective_mat: &Mat4, width: u32, height: u32) -> Vec<Fragment>
+/// Bits of subpixel precision of vertex positions on the screen
+pub const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+const SUBPIXEL_HALF: i64 = SUBPIXEL_ONE / 2;
+
+/// Most samples per pixel of multisample anti-aliasing
+pub const MAX_SAMPLES: usize = 8;
+
+/// Samples per pixel of multisample anti-aliasing, `X1` turns it off
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Msaa
+{
+    X1,
+    X2,
+    X4,
+    X8,
+}
+
+impl Msaa
 {
-    let w_f = width as f32;
-    let h_f = height as f32;
-    let mut fragment_arr: Vec<Vec<Fragment>> = triangles_ec.par_iter().map(|triangle_ec| {
-
-        let vs = vec![&triangle_ec.v1.position, &triangle_ec.v2.position, &triangle_ec.v3.position];
-        let vs_dc: Vec<Vec4> = vs.iter().map(|p| {
-            let mut v_sc = perspective_mat.mat_vec_dot(*p);
-            v_sc.scalar_div_( v_sc.w()); //normalize self
-            let v_dc = Vec4::new_xyzw((v_sc.x() + 1.0) * 0.5 * w_f,
-                                      (v_sc.y() + 1.0) * 0.5 * h_f,
-                                      -1.0 / v_sc.z(), // for perspective correctness, precompute 1/z //
-                                      1.0);
-            return v_dc;
-        }).collect();
-
-        let v0_dc = vs_dc.get(0).unwrap();
-        let v1_dc = vs_dc.get(1).unwrap();
-        let v2_dc = vs_dc.get(2).unwrap();
-        let area = triangle_area(v0_dc, v1_dc, v2_dc);
-
-        let (x_min, x_max) = get_min_max(v0_dc.x(), v1_dc.x(), v2_dc.x(), w_f, 0.0);
-        let (y_min, y_max) = get_min_max(v0_dc.y(), v1_dc.y(), v2_dc.y(), h_f, 0.0);
-        let mut fragments = Vec::new();
-        for i in x_min..x_max
+    /// `None` for counts other than 1, 2, 4 and 8
+    pub fn from_samples(samples: u32) -> Option<Self>
+    {
+        match samples {
+            1 => Some(Msaa::X1),
+            2 => Some(Msaa::X2),
+            4 => Some(Msaa::X4),
+            8 => Some(Msaa::X8),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn samples(&self) -> usize
+    {
+        sample_pattern(*self).len()
+    }
+}
+
+/// Sample positions within a pixel, in 1/16 of a pixel from its center
+///
+/// These are the standard patterns of Direct3D, mirrored vertically since y goes up here.
+pub fn sample_pattern(msaa: Msaa) -> &'static [(i64, i64)]
+{
+    match msaa {
+        Msaa::X1 => &[(0, 0)],
+        Msaa::X2 => &[(4, -4), (-4, 4)],
+        Msaa::X4 => &[(-2, 6), (6, 2), (-6, -2), (2, -6)],
+        Msaa::X8 => &[(1, 3), (-1, -3), (5, -1), (-3, 5), (-5, -5), (-7, 1), (3, -7), (7, 7)],
+    }
+}
+
+/// Snaps a coordinate in pixels to fixed point
+#[inline]
+fn to_fixed(x: f32) -> i64
+{
+    (x * SUBPIXEL_ONE as f32).round() as i64
+}
+
+/// Edge function of a triangle edge in fixed point, positive on the inner side
+///
+/// The value is stepped from pixel center to pixel center by adding `step_x` or `step_y`.
+#[derive(Debug, Copy, Clone)]
+struct Edge
+{
+    /// value at the center of pixel (0, 0)
+    origin: i64,
+    /// change of the value one pixel to the right
+    step_x: i64,
+    /// change of the value one pixel up
+    step_y: i64,
+    /// 0 for top and left edges and -1 for the others, so a pixel center on an edge shared by two
+    /// triangles belongs to exactly one of them
+    bias: i64,
+}
+
+impl Edge
+{
+    /// The edge from `a` to `b` of a counter-clockwise triangle, with positions in fixed point
+    fn new(a: (i64, i64), b: (i64, i64)) -> Self
+    {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        // y goes up, so the interior is below top edges, which go left, and right of left edges, which go down
+        let top_left = dy < 0 || (dy == 0 && dx < 0);
+        Edge {
+            origin: dx * (SUBPIXEL_HALF - a.1) - dy * (SUBPIXEL_HALF - a.0),
+            step_x: -dy * SUBPIXEL_ONE,
+            step_y: dx * SUBPIXEL_ONE,
+            bias: if top_left { 0 } else { -1 },
+        }
+    }
+
+    #[inline]
+    fn at(&self, i: u32, j: u32) -> i64
+    {
+        self.origin + i as i64 * self.step_x + j as i64 * self.step_y
+    }
+
+    /// Change of the value from a pixel center to a point `dx` and `dy` away, in fixed point
+    #[inline]
+    fn offset(&self, dx: i64, dy: i64) -> i64
+    {
+        (dx * self.step_x + dy * self.step_y) >> SUBPIXEL_BITS
+    }
+}
+
+/// A clipped triangle in device coordinates, ready to be binned and rasterized
+///
+/// Corners are stored counter-clockwise on the screen, whatever their winding in the mesh.
+pub struct ScreenTriangle
+{
+    /// the object the triangle belongs to, for shading
+    pub object_idx: usize,
+    vertices: [ClipVertex; 3],
+    /// x and y in pixels, z in NDC and w holding 1 / w of clip space
+    vertices_dc: [Vec4; 3],
+    /// x and y snapped to fixed point with `SUBPIXEL_BITS`
+    vertices_fixed: [(i64, i64); 3],
+    /// twice the area in fixed point, positive
+    area: i64,
+    /// covered pixels lie within x_min..x_max and y_min..y_max
+    pub x_min: u32,
+    pub x_max: u32,
+    pub y_min: u32,
+    pub y_max: u32,
+}
+
+impl ScreenTriangle
+{
+    /// Projects a triangle that lies inside of the view frustum, `None` if it covers no area
+    fn new(v0: &ClipVertex, v1: &ClipVertex, v2: &ClipVertex, width: u32, height: u32, object_idx: usize) -> Option<Self>
+    {
+        let (w_f, h_f) = (width as f32, height as f32);
+        let to_dc = |v: &ClipVertex| {
+            let v_cc = &v.position_cc;
+            // attributes are linear in screen space only after dividing them by w, so keep 1/w around
+            let one_over_w = 1.0 / v_cc.w();
+            return Vec4::new_xyzw((v_cc.x() * one_over_w + 1.0) * 0.5 * w_f,
+                                  (v_cc.y() * one_over_w + 1.0) * 0.5 * h_f,
+                                  v_cc.z() * one_over_w,
+                                  one_over_w);
+        };
+        let mut vertices = [*v0, *v1, *v2];
+        let mut vertices_dc = [to_dc(v0), to_dc(v1), to_dc(v2)];
+        let mut vertices_fixed = [(0, 0); 3];
+        for (fixed, v_dc) in vertices_fixed.iter_mut().zip(vertices_dc.iter())
         {
-            for j in y_min..y_max
-            {
-                let p = Vec4::new_xyzw((i as f32) + 0.5,
-                                       // (height - j) as f32 + 0.5, //seems weird
-                                       j as f32 + 0.5,
-                                       0.0, 0.0);
-                let mut w0 = triangle_area(v1_dc, v2_dc, &p);
-                let mut w1 = triangle_area(v2_dc, v0_dc, &p);
-                let mut w2 = triangle_area(v0_dc, v1_dc, &p);
-                if w0 >= 0.0 && w1 >= 0.0 && w2 >= 0.0
-                {
-                    w0 /= area;
-                    w1 /= area;
-                    w2 /= area;
-                    let z = 1.0 / (w0 * v0_dc.z() + w1 * v1_dc.z() + w2 * v2_dc.z());
-                    let normal = interpolate((w0, w1, w2), (&triangle_ec.n1.vec, &triangle_ec.n2.vec, &triangle_ec.n3.vec), z);
-                    let mut coord_ec = interpolate((w0, w1, w2), (&triangle_ec.v1.position, &triangle_ec.v2.position, &triangle_ec.v3.position), z);
-                    coord_ec.scalar_div_(coord_ec.w());
-                    let f = Fragment {
-                        x: i,
-                        y: j,
-                        z,
-                        coord_ec,
-                        normal_ec: normal,
-                    };
-                    fragments.push(f);
-                }
-            }
+            *fixed = (to_fixed(v_dc.x()), to_fixed(v_dc.y()));
+        }
+        let [p0, p1, p2] = vertices_fixed;
+        let mut area = (p1.0 - p0.0) * (p2.1 - p0.1) - (p1.1 - p0.1) * (p2.0 - p0.0);
+        if area == 0
+        {
+            return None;
         }
-        return fragments;
+        if area < 0
+        {
+            vertices.swap(1, 2);
+            vertices_dc.swap(1, 2);
+            vertices_fixed.swap(1, 2);
+            area = -area;
+        }
+        // pixel i is covered only if one of its samples, which lie strictly inside of i..i + 1, is within the bounds
+        let first_pixel = |min: i64, size: u32| (min >> SUBPIXEL_BITS).max(0).min(size as i64) as u32;
+        let last_pixel = |max: i64, size: u32| (((max - 1) >> SUBPIXEL_BITS) + 1).max(0).min(size as i64) as u32;
+        let xs = vertices_fixed.iter().map(|p| p.0);
+        let ys = vertices_fixed.iter().map(|p| p.1);
+        return Some(ScreenTriangle {
+            object_idx,
+            vertices,
+            vertices_dc,
+            vertices_fixed,
+            area,
+            x_min: first_pixel(xs.clone().min().unwrap(), width),
+            x_max: last_pixel(xs.max().unwrap(), width),
+            y_min: first_pixel(ys.clone().min().unwrap(), height),
+            y_max: last_pixel(ys.max().unwrap(), height),
+        });
+    }
+
+    /// Shifts the depth of the triangle by `factor` times its steepest depth slope in pixels, plus
+    /// `units` times the smallest depth difference that can be told apart around it
+    pub fn offset_depth(&mut self, offset: &PolygonOffset)
+    {
+        if offset.factor == 0.0 && offset.units == 0.0
+        {
+            return;
+        }
+        let [v0, v1, v2] = &self.vertices_dc;
+        let (e1, e2) = (v1._minus(v0), v2._minus(v0));
+        let area = e1.x() * e2.y() - e2.x() * e1.y();
+        let dz_dx = (e1.z() * e2.y() - e2.z() * e1.y()) / area;
+        let dz_dy = (e2.z() * e1.x() - e1.z() * e2.x()) / area;
+        let (z_min, z_max) = self.depth_range();
+        let resolution = f32::max(f32::max(z_min.abs(), z_max.abs()) * f32::EPSILON, f32::MIN_POSITIVE);
+        let shift = offset.factor * f32::max(dz_dx.abs(), dz_dy.abs()) + offset.units * resolution;
+        for v in self.vertices_dc.iter_mut()
+        {
+            v.set_z(v.z() + shift);
+        }
+    }
+
+    /// Nearest and farthest NDC depth of the triangle
+    pub fn depth_range(&self) -> (f32, f32)
+    {
+        let [v0, v1, v2] = &self.vertices_dc;
+        return (v0.z().min(v1.z()).min(v2.z()), v0.z().max(v1.z()).max(v2.z()));
+    }
+
+    /// Interpolates the attributes at the center of pixel `x`, `y`, which the triangle covers
+    ///
+    /// Texture coordinates are differentiated across the 2x2 quad of the pixel like on GPUs, its
+    /// pixels outside of the triangle included.
+    pub fn fragment(&self, x: u32, y: u32) -> Fragment
+    {
+        let [v0, v1, v2] = &self.vertices;
+        let edges = self.edges();
+        let at = |i: u32, j: u32| [edges[0].at(i, j), edges[1].at(i, j), edges[2].at(i, j)];
+        let e = at(x, y);
+        let (pw, w) = self.perspective_weights(&e);
+        let mut varyings = interpolate(pw, (&v0.varyings, &v1.varyings, &v2.varyings), w);
+        if let Some(slot) = v0.varyings.texcoord_slot()
+        {
+            let t = [v0.varyings.vec2(slot), v1.varyings.vec2(slot), v2.varyings.vec2(slot)];
+            let uv_at = |e: &[i64; 3]| {
+                let (pw, w) = self.perspective_weights(e);
+                return interpolate(pw, (&t[0], &t[1], &t[2]), w);
+            };
+            let (qx, qy) = (x & !1, y & !1);
+            let uv_00 = uv_at(&at(qx, qy));
+            let (duv_dx, duv_dy) = (uv_at(&at(qx + 1, qy))._minus(&uv_00), uv_at(&at(qx, qy + 1))._minus(&uv_00));
+            varyings.push_texcoord_derivatives(&duv_dx, &duv_dy);
+        }
+        return Fragment {
+            x,
+            y,
+            z: self.depth_at(&e, self.depth_range()),
+            varyings,
+        };
+    }
+
+    /// The edge facing each corner, whose value over the area is the barycentric weight of that corner
+    #[inline]
+    fn edges(&self) -> [Edge; 3]
+    {
+        let [p0, p1, p2] = self.vertices_fixed;
+        return [Edge::new(p1, p2), Edge::new(p2, p0), Edge::new(p0, p1)];
+    }
+
+    #[inline]
+    fn barycentric(&self, e: &[i64; 3]) -> (f32, f32, f32)
+    {
+        let area = self.area as f32;
+        return (e[0] as f32 / area, e[1] as f32 / area, e[2] as f32 / area);
+    }
+
+    /// NDC depth, which is linear in screen space
+    ///
+    /// Rounding must not take it out of `z_range` of the corners, which hierarchical Z relies on.
+    #[inline]
+    fn depth_at(&self, e: &[i64; 3], z_range: (f32, f32)) -> f32
+    {
+        let [v0, v1, v2] = &self.vertices_dc;
+        let (w0, w1, w2) = self.barycentric(e);
+        return (w0 * v0.z() + w1 * v1.z() + w2 * v2.z()).max(z_range.0).min(z_range.1);
+    }
+
+    /// Weights and reciprocal of their sum for `interpolate`
+    #[inline]
+    fn perspective_weights(&self, e: &[i64; 3]) -> ((f32, f32, f32), f32)
+    {
+        let [v0, v1, v2] = &self.vertices_dc;
+        let w = self.barycentric(e);
+        let pw = (w.0 * v0.w(), w.1 * v1.w(), w.2 * v2.w());
+        return (pw, 1.0 / (pw.0 + pw.1 + pw.2));
+    }
+}
+
+/// Clips the triangles against the view frustum and projects them to the screen
+///
+/// A triangle crossing the frustum may turn into several screen triangles.
+pub fn setup_triangles(triangles_ec: &Vec<Triangle>, perspective_mat: &Mat4, depth_range: DepthRange,
+                       width: u32, height: u32, object_idx: usize) -> Vec<ScreenTriangle>
+{
+    let screen_triangles: Vec<ScreenTriangle> = triangles_ec.par_iter().flat_map(|triangle_ec| {
+        let (positions_cc, codes) = triangle_ec.clip
+            .unwrap_or_else(|| triangle_ec.project_corners(perspective_mat, depth_range));
+        let clip_vertex = |k: usize| ClipVertex {
+            position_cc: positions_cc[k],
+            varyings: triangle_ec.varyings[k],
+        };
+        let triangle_cc = [clip_vertex(0), clip_vertex(1), clip_vertex(2)];
+        let polygon = clip_triangle(&triangle_cc, codes, depth_range);
+        // the clipped polygon is convex, so a fan around its first corner covers it
+        let screen_triangles: Vec<ScreenTriangle> = (2..polygon.len())
+            .filter_map(|k| ScreenTriangle::new(&polygon[0], &polygon[k - 1], &polygon[k], width, height, object_idx))
+            .collect();
+        return screen_triangles;
     }).collect();
+    return screen_triangles;
+}
 
+/// Turns the triangles into fragments, clipping them against the view frustum first
+///
+/// Every fragment is kept, there is no depth test. Tests compare the tiled rasterizer of
+/// `Renderer` with this list of fragments.
+#[cfg(test)]
+pub fn rasterization(triangles_ec: &Vec<Triangle>, perspective_mat: &Mat4, width: u32, height: u32) -> Vec<Fragment>
+{
     let mut fragments = Vec::new();
-    for frags in fragment_arr.iter_mut()
+    for triangle in setup_triangles(triangles_ec, perspective_mat, DepthRange::MinusOneToOne, width, height, 0).iter()
     {
-        fragments.append(frags);
+        rasterize_triangle(triangle, (0, width), (0, height), sample_pattern(Msaa::X1), |x, y, _, _| fragments.push(triangle.fragment(x, y)));
     }
     return fragments;
 }
 
-pub fn raster(triangle_sc: &Triangle) -> Vec<Fragment>
+/// Hands the covered samples of every pixel of the triangle within `x_range` and `y_range` to `visit`
+///
+/// Ranges exclude their upper bound and must start on even pixels, to keep 2x2 quads aligned.
+/// Coverage is decided on fixed point edge functions, which are stepped incrementally from
+/// quad to quad and from row to row, at every sample of `sample_pattern`. `visit` gets the
+/// pixel, the bit set of covered samples and their depths before any attribute is interpolated,
+/// so occluded pixels cost next to nothing. `ScreenTriangle::fragment` interpolates the
+/// attributes of the pixels that need them.
+pub fn rasterize_triangle<V>(triangle: &ScreenTriangle, x_range: (u32, u32), y_range: (u32, u32),
+                             samples: &[(i64, i64)], mut visit: V)
+    where V: FnMut(u32, u32, u32, &[f32])
 {
-    let mut fragments = Vec::<Fragment>::new();
-    if triangle_sc.v1.y() == triangle_sc.v2.y() && triangle_sc.v1.y() == triangle_sc.v3.y()
+    debug_assert!(x_range.0 % 2 == 0 && y_range.0 % 2 == 0);
+    debug_assert!(!samples.is_empty() && samples.len() <= MAX_SAMPLES);
+    let (x_min, x_max) = (u32::max(triangle.x_min, x_range.0), u32::min(triangle.x_max, x_range.1));
+    let (y_min, y_max) = (u32::max(triangle.y_min, y_range.0), u32::min(triangle.y_max, y_range.1));
+    let edges = triangle.edges();
+    let step_x = [edges[0].step_x, edges[1].step_x, edges[2].step_x];
+    let step_y = [edges[0].step_y, edges[1].step_y, edges[2].step_y];
+    let bias = [edges[0].bias, edges[1].bias, edges[2].bias];
+    let center_only = samples == [(0, 0)];
+    // change of the edge values from the pixel center to each sample
+    let mut sample_offsets = [[0i64; 3]; MAX_SAMPLES];
+    for (offset, (dx, dy)) in sample_offsets.iter_mut().zip(samples.iter())
     {
-        return fragments;
-    } else {
-        let mut v = vec![triangle_sc.v1, triangle_sc.v2, triangle_sc.v3];
-        v.sort_by(|a, b| (*a).position.y().partial_cmp(&(*b).position.y()).unwrap());
-        let y_min = (v.get(0).unwrap().y()) as i32;
-        let y_mid = (v.get(1).unwrap().y()) as i32;
-        let y_max = (v.get(2).unwrap().y()) as i32;
-
-        let x_min = (v.get(0).unwrap().x()) as i32;
-        let x_mid = (v.get(1).unwrap().x()) as i32;
-        let x_max = (v.get(2).unwrap().x()) as i32;
-
-        let y_height = y_max - y_min;
-        let y_height_f = y_height as f32;
-        for i in 0..y_height
+        let (dx, dy) = (dx * SUBPIXEL_ONE / 16, dy * SUBPIXEL_ONE / 16);
+        *offset = [edges[0].offset(dx, dy), edges[1].offset(dx, dy), edges[2].offset(dx, dy)];
+    }
+    let sample_offsets = &sample_offsets[..samples.len()];
+    let z_range = triangle.depth_range();
+    // pixels are visited in 2x2 quads, whose edge values are evaluated together
+    let (x_start, y_start) = (x_min & !1, y_min & !1);
+    let mut row = [edges[0].at(x_start, y_start), edges[1].at(x_start, y_start), edges[2].at(x_start, y_start)];
+    for qj in (y_start..y_max).step_by(2)
+    {
+        let mut e = row;
+        for qi in (x_start..x_max).step_by(2)
         {
-            let half = i > y_mid - y_min || y_mid == y_min;
-            let seg_height = match half {
-                true => y_max - y_mid,
-                false => y_mid - y_min
-            };
-            let alpha = (i as f32) / y_height_f;
-            let beta = ((i as f32) - if half { (y_mid - y_min) as f32 } else { 0.0_f32 }) / (seg_height as f32);
-            let ax = x_min + ((x_max - x_min) as f32 * alpha) as i32;
-            let bx = match half {
-                true => x_mid + ((x_max - x_mid) as f32 * beta) as i32,
-                false => x_min + ((x_mid - x_min) as f32 * beta) as i32
-            };
-            let (x_start, x_stop) = if ax > bx { (bx, ax) } else { (ax, bx) };
-            for j in x_start..(x_stop + 1)
+            let quad = [(qi, qj), (qi + 1, qj), (qi, qj + 1), (qi + 1, qj + 1)];
+            let (quad_e, inside) = quad_edge_values(&e, &step_x, &step_y, &bias);
+            // bit c of covered[s] is set when sample s of pixel c of the quad is covered
+            let mut covered = [inside; MAX_SAMPLES];
+            if !center_only
             {
-                fragments.push(Fragment {
-                    x: j as u32,
-                    y: (y_min + i) as u32,
-                    normal_ec: Vec4::new(0.0),
-                    coord_ec: Vec4::new(0.0),
-                    z: 0.0, //TODO: interpolate z
-                })
+                for (s, offset) in sample_offsets.iter().enumerate()
+                {
+                    let e_sample = [e[0] + offset[0], e[1] + offset[1], e[2] + offset[2]];
+                    covered[s] = quad_edge_values(&e_sample, &step_x, &step_y, &bias).1;
+                }
             }
+            for k in 0..3
+            {
+                e[k] += 2 * step_x[k];
+            }
+            for c in 0..4
+            {
+                let (i, j) = quad[c];
+                if i < x_min || i >= x_max || j < y_min || j >= y_max
+                {
+                    continue;
+                }
+                // bit s of the mask is set when sample s of the pixel is covered
+                let mask = (0..samples.len()).fold(0u32, |mask, s| mask | ((covered[s] >> c) as u32 & 1) << s);
+                if mask == 0
+                {
+                    continue;
+                }
+                let mut depths = [0.0; MAX_SAMPLES];
+                for (s, offset) in sample_offsets.iter().enumerate()
+                {
+                    if mask & (1 << s) != 0
+                    {
+                        depths[s] = triangle.depth_at(&[quad_e[c][0] + offset[0], quad_e[c][1] + offset[1], quad_e[c][2] + offset[2]], z_range);
+                    }
+                }
+                visit(i, j, mask, &depths[..samples.len()]);
+            }
+        }
+        for k in 0..3
+        {
+            row[k] += 2 * step_y[k];
         }
-        return fragments;
     }
 }
 
@@ -291,6 +963,8 @@ pub fn triangle_area(a: &Vec4, b: &Vec4, c: &Vec4) -> f32
 
 #[cfg(test)]
 mod test {
+    use crate::transformations::perspective;
+
     use super::*;
 
     #[test]
@@ -300,4 +974,236 @@ mod test {
         let v3 = Vec4::new(0.0);
         println!("{}", triangle_area(&v1, &v2, &v3));
     }
-}
\ No newline at end of file
+
+    /// Hands the eye space position of the vertex to the fragments
+    fn position_varyings(vertex: &Vertex) -> Varyings
+    {
+        let mut varyings = Varyings::new();
+        varyings.push_vec4(&vertex.position).unwrap();
+        return varyings;
+    }
+
+    #[test]
+    fn test_light_kinds() {
+        let point = Vec3::new(0.0);
+        // point lights fade with the distance
+        let mut light = Light::point(Vec3::new_xyz(0.0, 4.0, 0.0));
+        light.attenuation = Vec3::new_xyz(0.0, 0.0, 1.0);
+        let (direction, color) = light.incident(&point);
+        assert_eq!((direction.y(), color.r()), (1.0, 1.0 / 16.0));
+        // directional lights do not
+        let mut light = Light::directional(Vec3::new_xyz(0.0, 0.0, -2.0));
+        light.color = Vec3::new_rgb(1.0, 0.5, 0.0);
+        light.intensity = 2.0;
+        let (direction, color) = light.incident(&Vec3::new(100.0));
+        assert_eq!((direction.z(), color.r(), color.g()), (1.0, 2.0, 1.0));
+        // spot lights are full inside of the inner cone, off outside of the outer cone and in between across
+        let light = Light::spot(Vec3::new_xyz(0.0, 1.0, 0.0), Vec3::new_xyz(0.0, -1.0, 0.0),
+                                20f32.to_radians(), 40f32.to_radians());
+        let strength = |x: f32| light.incident(&Vec3::new_xyz(x, 0.0, 0.0)).1.r();
+        assert_eq!(strength(0.0), 1.0);
+        assert_eq!(strength(1.0), 0.0);
+        assert!(strength(30f32.to_radians().tan()) > 0.0 && strength(30f32.to_radians().tan()) < 1.0);
+        assert!(strength(0.5) > strength(0.6));
+    }
+
+    #[test]
+    fn test_lights_add_up() {
+        let material = Material::new(Vec3::new(0.5), Vec3::new(1.0), Vec3::new(0.0), Vec3::new(0.0), 16.0);
+        let normal = Vec3::new_xyz(0.0, 0.0, 1.0);
+        let shade = |lights: &[Light]| lighting(LightingModel::Phong, &Vec3::new(0.0), &normal, &normal,
+                                                &material.diffuse, &material, lights, &Vec3::new(0.2)).r();
+        let above = Light::directional(Vec3::new_xyz(0.0, 0.0, -1.0));
+        let below = Light::directional(Vec3::new_xyz(0.0, 0.0, 1.0));
+        assert_eq!(shade(&[]), 0.1);
+        assert_eq!(shade(&[above]), 1.1);
+        assert_eq!(shade(&[above, above]), 2.1);
+        // light from behind the surface adds nothing
+        assert_eq!(shade(&[above, below]), 1.1);
+    }
+
+    #[test]
+    fn test_lighting_models() {
+        let material = Material::new(Vec3::new(0.0), Vec3::new(0.5), Vec3::new(1.0), Vec3::new(0.0), 32.0);
+        let normal = Vec3::new_xyz(0.0, 0.0, 1.0);
+        let lights = [Light::directional(Vec3::new_xyz(-1.0, 0.0, -1.0))];
+        let shade = |model: LightingModel, material: &Material, view: &Vec3| {
+            lighting(model, &Vec3::new(0.0), &normal, &view.normalize(), &material.diffuse, material, &lights,
+                     &Vec3::new(0.0)).r()
+        };
+        let mirror = Vec3::new_xyz(-1.0, 0.0, 1.0);
+        let aside = Vec3::new_xyz(-0.3, 0.0, 1.0);
+        let diffuse = 0.5 * std::f32::consts::FRAC_1_SQRT_2;
+        for model in [LightingModel::Phong, LightingModel::BlinnPhong, LightingModel::Pbr].iter()
+        {
+            // the highlight is along the mirrored light direction
+            assert!(shade(*model, &material, &mirror) > shade(*model, &material, &aside), "{:?}", model);
+            assert!(shade(*model, &material, &aside) > 0.9 * diffuse, "{:?}", model);
+        }
+        // Blinn-Phong spreads the highlight wider for the same exponent
+        assert!(shade(LightingModel::BlinnPhong, &material, &aside) > shade(LightingModel::Phong, &material, &aside));
+
+        // rough surfaces spread the highlight of PBR materials, metals lose their diffuse light
+        let smooth = material.clone().with_metallic_roughness(0.0, 0.2);
+        let rough = material.clone().with_metallic_roughness(0.0, 0.8);
+        assert!(shade(LightingModel::Pbr, &smooth, &mirror) > shade(LightingModel::Pbr, &rough, &mirror));
+        assert!(shade(LightingModel::Pbr, &smooth, &aside) < shade(LightingModel::Pbr, &rough, &aside));
+        // metals tint their highlight with the base color, while that of dielectrics stays white
+        let highlight = |metallic: f32| {
+            let red = Material::new(Vec3::new(0.0), Vec3::new_rgb(1.0, 0.0, 0.0), Vec3::new(1.0), Vec3::new(0.0), 32.0)
+                .with_metallic_roughness(metallic, 0.3);
+            lighting(LightingModel::Pbr, &Vec3::new(0.0), &normal, &mirror.normalize(), &red.diffuse, &red, &lights,
+                     &Vec3::new(0.0))
+        };
+        assert!(highlight(1.0).g() < 0.1 * highlight(0.0).g());
+        assert!(highlight(1.0).r() > highlight(0.0).r());
+        // the energy reflected by a white matte surface does not exceed that of Phong
+        let white = Material::new(Vec3::new(0.0), Vec3::new(1.0), Vec3::new(0.0), Vec3::new(0.0), 1.0)
+            .with_metallic_roughness(0.0, 1.0);
+        assert!(shade(LightingModel::Pbr, &white, &aside) <= shade(LightingModel::Phong, &white, &aside) + 1e-4);
+    }
+
+    #[test]
+    fn test_perspective_correct_interpolation() {
+        // a triangle receding from z = -1 to z = -5, with u growing along x and v along y
+        let vertices = vec![
+            Vertex { position: Vec4::new_xyzw(-1.0, -1.0, -1.0, 1.0), idx: 0 },
+            Vertex { position: Vec4::new_xyzw(4.0, -1.0, -5.0, 1.0), idx: 1 },
+            Vertex { position: Vec4::new_xyzw(-1.0, 1.0, -1.0, 1.0), idx: 2 },
+        ];
+        let triangle = Triangle::new(&vertices[0], &vertices[1], &vertices[2])
+            .with_varyings(position_varyings(&vertices[0]), position_varyings(&vertices[1]), position_varyings(&vertices[2]))
+            .with_texcoords(Vec2::new_uv(0.0, 0.0), Vec2::new_uv(1.0, 0.0), Vec2::new_uv(0.0, 1.0));
+        let (width, height) = (64, 64);
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+        let fragments = rasterization(&vec![triangle], &perspective_mat, width, height);
+        assert!(!fragments.is_empty());
+        for f in fragments.iter()
+        {
+            // the interpolated eye space position projects back onto the pixel center, up to the
+            // snapping of the corners to subpixels
+            let coord_ec = f.varyings.vec4(0);
+            let mut p = perspective_mat.mat_vec_dot(&coord_ec);
+            p.scalar_div_(p.w());
+            let subpixel = 1.0 / SUBPIXEL_ONE as f32;
+            assert!(((p.x() + 1.0) * 0.5 * width as f32 - (f.x as f32 + 0.5)).abs() < subpixel);
+            assert!(((p.y() + 1.0) * 0.5 * height as f32 - (f.y as f32 + 0.5)).abs() < subpixel);
+            assert!((p.z() - f.z).abs() < 1e-4);
+            // and the texture coordinates follow the surface rather than the screen
+            let (uv, _, _) = f.texcoord().unwrap();
+            assert!((uv.u() - (coord_ec.x() + 1.0) / 5.0).abs() < 1e-4);
+            assert!((uv.v() - (coord_ec.y() + 1.0) / 2.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_rasterize_triangle_crossing_the_near_plane() {
+        // the last corner is behind the camera
+        let vertices = vec![
+            Vertex { position: Vec4::new_xyzw(-1.0, -1.0, -2.0, 1.0), idx: 0 },
+            Vertex { position: Vec4::new_xyzw(1.0, -1.0, -2.0, 1.0), idx: 1 },
+            Vertex { position: Vec4::new_xyzw(0.0, -1.0, 3.0, 1.0), idx: 2 },
+        ];
+        let triangle = Triangle::new(&vertices[0], &vertices[1], &vertices[2])
+            .with_varyings(position_varyings(&vertices[0]), position_varyings(&vertices[1]), position_varyings(&vertices[2]));
+        let near = 0.5;
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.0, near, 10.0);
+        let fragments = rasterization(&vec![triangle], &perspective_mat, 64, 64);
+        assert!(!fragments.is_empty());
+        for f in fragments.iter()
+        {
+            assert!(f.x < 64 && f.y < 32);
+            assert!(f.z >= -1.0 - 1e-5 && f.z <= 1.0);
+            assert!(f.varyings.vec4(0).z() <= -near + 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_uv_derivatives_of_quads() {
+        // a screen aligned triangle 2 units away, 1 unit on screen is 16 pixels
+        let vertices = vec![
+            Vertex { position: Vec4::new_xyzw(-2.0, -2.0, -2.0, 1.0), idx: 0 },
+            Vertex { position: Vec4::new_xyzw(2.0, -2.0, -2.0, 1.0), idx: 1 },
+            Vertex { position: Vec4::new_xyzw(-2.0, 2.0, -2.0, 1.0), idx: 2 },
+        ];
+        let triangle = Triangle::new(&vertices[0], &vertices[1], &vertices[2])
+            .with_texcoords(Vec2::new_uv(0.0, 0.0), Vec2::new_uv(2.0, 0.0), Vec2::new_uv(0.0, 4.0));
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+        let fragments = rasterization(&vec![triangle], &perspective_mat, 64, 64);
+        assert!(!fragments.is_empty());
+        for f in fragments.iter()
+        {
+            let (_, duv_dx, duv_dy) = f.texcoord().unwrap();
+            assert!((duv_dx.u() - 2.0 / 64.0).abs() < 1e-5 && duv_dx.v().abs() < 1e-5);
+            assert!(duv_dy.u().abs() < 1e-5 && (duv_dy.v() - 4.0 / 64.0).abs() < 1e-5);
+        }
+        // fragments along the diagonal edge have quad neighbours outside of the triangle, pixel
+        // centers on the edge itself are left out as it is neither a top nor a left edge
+        assert!(fragments.iter().any(|f| f.x + f.y == 62));
+        assert!(!fragments.iter().any(|f| f.x + f.y == 63));
+    }
+
+    #[test]
+    fn test_shared_edges_are_covered_once() {
+        let (width, height) = (16, 16);
+        // positions in pixels, on pixel centers so that every edge runs through some of them
+        let vertex = |x: f32, y: f32| ClipVertex {
+            position_cc: Vec4::new_xyzw(x / 8.0 - 1.0, y / 8.0 - 1.0, 0.0, 1.0),
+            varyings: Varyings::new(),
+        };
+        let center = vertex(8.5, 8.5);
+        let corners = [vertex(2.5, 2.5), vertex(13.5, 2.5), vertex(13.5, 13.5), vertex(2.5, 13.5)];
+        // a fan around the center, with one triangle wound clockwise
+        let mut triangles: Vec<ScreenTriangle> = (0..4)
+            .filter_map(|k| ScreenTriangle::new(&center, &corners[k], &corners[(k + 1) % 4], width, height, 0))
+            .collect();
+        triangles.push(ScreenTriangle::new(&center, &corners[0], &corners[3], width, height, 0).unwrap());
+        triangles.remove(3);
+        let mut coverage = vec![0; (width * height) as usize];
+        for triangle in triangles.iter()
+        {
+            rasterize_triangle(triangle, (0, width), (0, height), sample_pattern(Msaa::X1),
+                               |x, y, _, _| coverage[(y * width + x) as usize] += 1);
+        }
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                // left and top edges of the square are in, right and bottom edges are out
+                let inside = x >= 2 && x < 13 && y > 2 && y <= 13;
+                assert_eq!(coverage[(y * width + x) as usize], if inside { 1 } else { 0 }, "pixel {} {}", x, y);
+            }
+        }
+
+        // every sample is covered once too, including those on the diagonals of the fan
+        for msaa in [Msaa::X2, Msaa::X4, Msaa::X8].iter()
+        {
+            let pattern = sample_pattern(*msaa);
+            let mut coverage = vec![0; (width * height) as usize * pattern.len()];
+            for triangle in triangles.iter()
+            {
+                rasterize_triangle(triangle, (0, width), (0, height), pattern, |x, y, mask, depths| {
+                    assert_eq!(depths.len(), pattern.len());
+                    for s in 0..pattern.len()
+                    {
+                        coverage[(y * width + x) as usize * pattern.len() + s] += (mask >> s) & 1;
+                    }
+                });
+            }
+            for y in 0..height
+            {
+                for x in 0..width
+                {
+                    for (s, (dx, dy)) in pattern.iter().enumerate()
+                    {
+                        let sample_x = x as f32 + 0.5 + *dx as f32 / 16.0;
+                        let sample_y = y as f32 + 0.5 + *dy as f32 / 16.0;
+                        let inside = sample_x > 2.5 && sample_x < 13.5 && sample_y > 2.5 && sample_y < 13.5;
+                        assert_eq!(coverage[(y * width + x) as usize * pattern.len() + s], inside as u32,
+                                   "sample {} of pixel {} {} with {:?}", s, x, y, msaa);
+                    }
+                }
+            }
+        }
+    }
+}
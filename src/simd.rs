@@ -0,0 +1,204 @@
+//! Hot loops with an SSE2 path, enabled by the `simd` cargo feature on x86_64
+//!
+//! Every function has a scalar fallback that does the same operations in the same order, so both
+//! paths give bit-identical results.
+
+use crate::data::{_Mat, Mat4, Vec4};
+
+/// Multiplies every point by `mat`, like `mat_vec_dot`
+pub fn transform_points(mat: &Mat4, points: &[Vec4]) -> Vec<Vec4>
+{
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    return sse2::transform_points(mat, points);
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    return scalar::transform_points(mat, points);
+}
+
+/// Edge function values at the four pixels of a 2x2 quad and the bit set of pixels inside of all edges
+///
+/// `e` holds the values of the three edges at the lower left pixel. Pixels are ordered (0, 0), (1, 0),
+/// (0, 1), (1, 1) and a pixel is inside when every value plus its bias is non-negative. The SIMD
+/// path adds the changes of a value across the quad in `i32` lanes, steps too large for them, from
+/// frames wider than 8192 pixels, take the scalar path.
+#[inline]
+pub fn quad_edge_values(e: &[i64; 3], step_x: &[i64; 3], step_y: &[i64; 3], bias: &[i64; 3]) -> ([[i64; 3]; 4], u8)
+{
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    return sse2::quad_edge_values(e, step_x, step_y, bias);
+    #[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+    return scalar::quad_edge_values(e, step_x, step_y, bias);
+}
+
+// with the feature, the scalar paths serve remainders and tests
+#[cfg_attr(all(feature = "simd", target_arch = "x86_64"), allow(dead_code))]
+mod scalar
+{
+    use super::*;
+
+    pub fn transform_points(mat: &Mat4, points: &[Vec4]) -> Vec<Vec4>
+    {
+        let row = |r: usize, p: &Vec4| mat._get_entry(r, 0) * p.x()
+            + mat._get_entry(r, 1) * p.y()
+            + mat._get_entry(r, 2) * p.z()
+            + mat._get_entry(r, 3) * p.w();
+        return points.iter().map(|p| Vec4::new_xyzw(row(0, p), row(1, p), row(2, p), row(3, p))).collect();
+    }
+
+    #[inline]
+    pub fn quad_edge_values(e: &[i64; 3], step_x: &[i64; 3], step_y: &[i64; 3], bias: &[i64; 3]) -> ([[i64; 3]; 4], u8)
+    {
+        let mut values = [*e; 4];
+        let mut mask = 0;
+        for c in 0..4
+        {
+            let mut signs = 0;
+            for k in 0..3
+            {
+                values[c][k] += (c as i64 & 1) * step_x[k] + (c as i64 >> 1) * step_y[k];
+                signs |= values[c][k] + bias[k];
+            }
+            if signs >= 0
+            {
+                mask |= 1 << c;
+            }
+        }
+        return (values, mask);
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", any(feature = "simd", test)))]
+mod sse2
+{
+    use std::arch::x86_64::*;
+
+    use super::*;
+
+    /// Transforms four points at a time, with their x, y, z and w in separate registers
+    pub fn transform_points(mat: &Mat4, points: &[Vec4]) -> Vec<Vec4>
+    {
+        let mut transformed = Vec::with_capacity(points.len());
+        let blocks = points.chunks_exact(4);
+        let rest = blocks.remainder();
+        // SSE2 is part of x86_64, so the intrinsics are always available
+        unsafe {
+            let mut m = [_mm_setzero_ps(); 16];
+            for (i, entry) in m.iter_mut().enumerate()
+            {
+                *entry = _mm_set1_ps(mat._get_entry(i / 4, i % 4));
+            }
+            for block in blocks
+            {
+                let x = _mm_set_ps(block[3].x(), block[2].x(), block[1].x(), block[0].x());
+                let y = _mm_set_ps(block[3].y(), block[2].y(), block[1].y(), block[0].y());
+                let z = _mm_set_ps(block[3].z(), block[2].z(), block[1].z(), block[0].z());
+                let w = _mm_set_ps(block[3].w(), block[2].w(), block[1].w(), block[0].w());
+                let mut rows = [[0.0f32; 4]; 4];
+                for (r, row) in rows.iter_mut().enumerate()
+                {
+                    let mut sum = _mm_mul_ps(m[4 * r], x);
+                    sum = _mm_add_ps(sum, _mm_mul_ps(m[4 * r + 1], y));
+                    sum = _mm_add_ps(sum, _mm_mul_ps(m[4 * r + 2], z));
+                    sum = _mm_add_ps(sum, _mm_mul_ps(m[4 * r + 3], w));
+                    _mm_storeu_ps(row.as_mut_ptr(), sum);
+                }
+                for i in 0..4
+                {
+                    transformed.push(Vec4::new_xyzw(rows[0][i], rows[1][i], rows[2][i], rows[3][i]));
+                }
+            }
+        }
+        transformed.append(&mut scalar::transform_points(mat, rest));
+        return transformed;
+    }
+
+    /// Tests each edge at the four pixels at once, on the `i32` changes of its value across the quad
+    ///
+    /// A pixel is outside of an edge when the change is below `-(e + bias)`, which is clamped to
+    /// the `i32` range so that far away edges stay all in or all out.
+    #[inline]
+    pub fn quad_edge_values(e: &[i64; 3], step_x: &[i64; 3], step_y: &[i64; 3], bias: &[i64; 3]) -> ([[i64; 3]; 4], u8)
+    {
+        if (0..3).any(|k| step_x[k].abs() + step_y[k].abs() > i32::MAX as i64)
+        {
+            return scalar::quad_edge_values(e, step_x, step_y, bias);
+        }
+        let mut values = [*e; 4];
+        unsafe {
+            let mut outside = _mm_setzero_si128();
+            for k in 0..3
+            {
+                let deltas = [0, step_x[k] as i32, step_y[k] as i32, (step_x[k] + step_y[k]) as i32];
+                let threshold = i64::max(i64::min(-(e[k] + bias[k]), i32::MAX as i64), i32::MIN as i64) as i32;
+                let lanes = _mm_loadu_si128(deltas.as_ptr() as *const __m128i);
+                outside = _mm_or_si128(outside, _mm_cmpgt_epi32(_mm_set1_epi32(threshold), lanes));
+                for c in 0..4
+                {
+                    values[c][k] += deltas[c] as i64;
+                }
+            }
+            return (values, !_mm_movemask_ps(_mm_castsi128_ps(outside)) as u8 & 0b1111);
+        }
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod test {
+    use crate::transformations::perspective;
+
+    use super::*;
+
+    /// Deterministic pseudo random numbers in [-1, 1)
+    fn numbers(n: usize) -> Vec<f32>
+    {
+        let mut state: u32 = 12345;
+        return (0..n).map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 23) as f32 - 1.0
+        }).collect();
+    }
+
+    #[test]
+    fn test_transform_points_matches_scalar() {
+        let mat = perspective(1.2, 1.5, 0.1, 100.0);
+        // 4 * 6 + 3 points, so the remainder goes through the scalar path too
+        let values = numbers(27 * 4);
+        let points: Vec<Vec4> = values.chunks(4).map(|v| Vec4::new_xyzw(v[0] * 50.0, v[1] * 50.0, v[2] * 50.0, 1.0 + v[3].abs())).collect();
+        let simd = sse2::transform_points(&mat, &points);
+        let scalar = scalar::transform_points(&mat, &points);
+        assert_eq!(simd.len(), points.len());
+        for (a, b) in simd.iter().zip(scalar.iter())
+        {
+            assert_eq!([a.x().to_bits(), a.y().to_bits(), a.z().to_bits(), a.w().to_bits()],
+                       [b.x().to_bits(), b.y().to_bits(), b.z().to_bits(), b.w().to_bits()]);
+        }
+    }
+
+    #[test]
+    fn test_quad_edge_values_match_scalar() {
+        let values = numbers(1000 * 12);
+        for v in values.chunks(12)
+        {
+            let int = |x: f32, scale: f32| (x * scale) as i64;
+            let e = [int(v[0], 1e4), int(v[1], 1e4), int(v[2], 1e4)];
+            let step_x = [int(v[3], 1e4), int(v[4], 1e4), int(v[5], 1e4)];
+            let step_y = [int(v[6], 1e4), int(v[7], 1e4), int(v[8], 1e4)];
+            let bias = [-((v[9] > 0.0) as i64), -((v[10] > 0.0) as i64), -((v[11] > 0.0) as i64)];
+            assert_eq!(sse2::quad_edge_values(&e, &step_x, &step_y, &bias), scalar::quad_edge_values(&e, &step_x, &step_y, &bias));
+        }
+        // values of zero are inside only without a bias
+        let (_, mask) = sse2::quad_edge_values(&[0, 0, 0], &[0, 0, -1], &[0, 0, 1], &[0, 0, 0]);
+        assert_eq!(mask, 0b1101);
+        let (_, mask) = sse2::quad_edge_values(&[0, 0, 0], &[0, 0, -1], &[0, 0, 1], &[0, 0, -1]);
+        assert_eq!(mask, 0b0100);
+        // values beyond the i32 range
+        let far = 1 << 40;
+        for e in [[far, far, far], [-far, far, far], [far, far, 1 - far]].iter()
+        {
+            let (step_x, step_y, bias) = ([0, 0, 1 << 20], [0, 0, -1], [0, 0, -1]);
+            assert_eq!(sse2::quad_edge_values(e, &step_x, &step_y, &bias), scalar::quad_edge_values(e, &step_x, &step_y, &bias));
+        }
+        // steps beyond the i32 range
+        let (e, step_x, step_y, bias) = ([0, 0, -far], [far, 0, 1 << 31], [0, -far, 1 << 31], [0, 0, 0]);
+        assert_eq!(sse2::quad_edge_values(&e, &step_x, &step_y, &bias), scalar::quad_edge_values(&e, &step_x, &step_y, &bias));
+    }
+}
@@ -0,0 +1,347 @@
+use std::io;
+use std::path::Path;
+
+use crate::buffer::FrameBuffer;
+use crate::data::{Add, Length, Product, ScalarDiv, ScalarMul, Vec2, Vec3};
+use crate::image;
+
+/// Upper bound of `Texture::max_anisotropy`
+pub const MAX_ANISOTROPY: u32 = 16;
+
+/// How a texture is sampled between texel centers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Filter
+{
+    Nearest,
+    Bilinear,
+    /// Bilinear samples of the two mip levels closest to the screen footprint, blended
+    Trilinear,
+}
+
+/// How texture coordinates outside of [0, 1] are mapped into the texture
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wrap
+{
+    Repeat,
+    Clamp,
+}
+
+/// One level of a mip chain, rows from bottom to top
+struct MipLevel
+{
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>,
+}
+
+impl MipLevel
+{
+    /// Box-filters 2x2 texels into one, an odd last row or column is folded into its neighbour
+    fn downsample(&self) -> MipLevel
+    {
+        let width = usize::max(1, self.width / 2);
+        let height = usize::max(1, self.height / 2);
+        let mut texels = Vec::with_capacity(width * height);
+        for y in 0..height
+        {
+            for x in 0..width
+            {
+                let xs = if x == width - 1 { 2 * x..self.width } else { 2 * x..2 * x + 2 };
+                let ys = if y == height - 1 { 2 * y..self.height } else { 2 * y..2 * y + 2 };
+                let mut sum = Vec3::new(0.0);
+                let mut count = 0;
+                for sy in ys
+                {
+                    for sx in xs.clone()
+                    {
+                        sum.add_(&self.texels[sy * self.width + sx]);
+                        count += 1;
+                    }
+                }
+                texels.push(sum.scalar_div(count as f32));
+            }
+        }
+        return MipLevel {
+            width,
+            height,
+            texels,
+        };
+    }
+
+    /// Texel at integer coordinates, which may lie outside of the level
+    #[inline]
+    fn texel(&self, x: i64, y: i64, mode: Wrap) -> Vec3
+    {
+        let x = wrap(x, self.width, mode);
+        let y = wrap(y, self.height, mode);
+        return self.texels[y * self.width + x];
+    }
+
+    fn nearest(&self, uv: &Vec2, mode: Wrap) -> Vec3
+    {
+        // texel centers sit at half-integer coordinates
+        let x = uv.u() * self.width as f32 - 0.5;
+        let y = uv.v() * self.height as f32 - 0.5;
+        return self.texel(x.round() as i64, y.round() as i64, mode);
+    }
+
+    fn bilinear(&self, uv: &Vec2, mode: Wrap) -> Vec3
+    {
+        let x = uv.u() * self.width as f32 - 0.5;
+        let y = uv.v() * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let bottom = lerp(&self.texel(x0, y0, mode), &self.texel(x0 + 1, y0, mode), tx);
+        let top = lerp(&self.texel(x0, y0 + 1, mode), &self.texel(x0 + 1, y0 + 1, mode), tx);
+        return lerp(&bottom, &top, ty);
+    }
+}
+
+/// An RGB image sampled with texture coordinates
+///
+/// (0, 0) is the bottom-left corner and (1, 1) the top-right corner, as in OBJ files.
+/// The whole mip chain is built up front, down to a single texel.
+pub struct Texture
+{
+    levels: Vec<MipLevel>,
+    pub filter: Filter,
+    pub wrap: Wrap,
+    /// Most samples taken along the longer axis of an elongated footprint, 1 disables anisotropic filtering
+    ///
+    /// Only used by trilinear filtering.
+    pub max_anisotropy: u32,
+}
+
+impl Texture
+{
+    /// `texels` are stored row by row, starting from the bottom row
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>) -> Self
+    {
+        assert!(width > 0 && height > 0);
+        assert_eq!(texels.len(), width * height);
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            texels,
+        }];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1
+            {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Texture {
+            levels,
+            filter: Filter::Trilinear,
+            wrap: Wrap::Repeat,
+            max_anisotropy: 1,
+        }
+    }
+
+    /// Loads a PNG or PPM file
+    pub fn load(path: &Path) -> io::Result<Self>
+    {
+        let frame = image::load(path)?;
+        return Ok(Texture::from(&frame));
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize
+    {
+        self.levels[0].width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize
+    {
+        self.levels[0].height
+    }
+
+    /// Number of mip levels, including the full resolution one
+    #[inline]
+    pub fn level_num(&self) -> usize
+    {
+        self.levels.len()
+    }
+
+    /// Samples the full resolution level, ignoring how large the fragment is on the texture
+    ///
+    /// Trilinear filtering falls back to bilinear filtering.
+    pub fn sample(&self, uv: &Vec2) -> Vec3
+    {
+        return match self.filter {
+            Filter::Nearest => self.levels[0].nearest(uv, self.wrap),
+            Filter::Bilinear | Filter::Trilinear => self.levels[0].bilinear(uv, self.wrap),
+        };
+    }
+
+    /// Samples with the screen space derivatives of `uv`, which pick the mip level for trilinear filtering
+    pub fn sample_grad(&self, uv: &Vec2, duv_dx: &Vec2, duv_dy: &Vec2) -> Vec3
+    {
+        if self.filter != Filter::Trilinear
+        {
+            return self.sample(uv);
+        }
+        let size = Vec2::new_xy(self.width() as f32, self.height() as f32);
+        let len_x = duv_dx.product(&size).get_length();
+        let len_y = duv_dy.product(&size).get_length();
+        let (major_len, minor_len, major_duv) = if len_x > len_y {
+            (len_x, len_y, duv_dx)
+        } else {
+            (len_y, len_x, duv_dy)
+        };
+        // spread several samples along the longer axis so that the level only has to cover the shorter one
+        let sample_num = if self.max_anisotropy > 1 && minor_len > 0.0 {
+            f32::min((major_len / minor_len).ceil(), self.max_anisotropy as f32) as u32
+        } else {
+            1
+        };
+        let lod = (major_len / sample_num as f32).log2();
+        let mut color = Vec3::new(0.0);
+        for i in 0..sample_num
+        {
+            let t = (i as f32 + 0.5) / sample_num as f32 - 0.5;
+            color.add_(&self.trilinear(&uv._add(&major_duv.scalar_mul(t)), lod));
+        }
+        return color.scalar_div(sample_num as f32);
+    }
+
+    fn trilinear(&self, uv: &Vec2, lod: f32) -> Vec3
+    {
+        let max_level = (self.levels.len() - 1) as f32;
+        // a NaN lod from degenerate derivatives ends up at the full resolution level
+        let lod = if lod > 0.0 { f32::min(lod, max_level) } else { 0.0 };
+        let level = lod.floor() as usize;
+        let fine = self.levels[level].bilinear(uv, self.wrap);
+        if level + 1 == self.levels.len()
+        {
+            return fine;
+        }
+        let coarse = self.levels[level + 1].bilinear(uv, self.wrap);
+        return lerp(&fine, &coarse, lod - level as f32);
+    }
+}
+
+impl From<&FrameBuffer> for Texture
+{
+    fn from(frame: &FrameBuffer) -> Self
+    {
+        let mut texels = Vec::with_capacity(frame.width() * frame.height());
+        for y in 0..frame.height()
+        {
+            for x in 0..frame.width()
+            {
+                texels.push(frame.get_f32(x, y));
+            }
+        }
+        return Texture::new(frame.width(), frame.height(), texels);
+    }
+}
+
+#[inline]
+fn wrap(i: i64, size: usize, mode: Wrap) -> usize
+{
+    let size = size as i64;
+    let i = match mode {
+        Wrap::Repeat => i.rem_euclid(size),
+        Wrap::Clamp => i.max(0).min(size - 1),
+    };
+    return i as usize;
+}
+
+#[inline]
+fn lerp(a: &Vec3, b: &Vec3, t: f32) -> Vec3
+{
+    a.scalar_mul(1.0 - t)._add(&b.scalar_mul(t))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// black, red on the bottom row, green, blue on the top row
+    fn texture() -> Texture
+    {
+        let mut texture = Texture::new(2, 2, vec![
+            Vec3::new_rgb(0.0, 0.0, 0.0), Vec3::new_rgb(1.0, 0.0, 0.0),
+            Vec3::new_rgb(0.0, 1.0, 0.0), Vec3::new_rgb(0.0, 0.0, 1.0),
+        ]);
+        texture.filter = Filter::Bilinear;
+        return texture;
+    }
+
+    /// white and black rows alternating, starting with white at the bottom
+    fn stripes(size: usize) -> Texture
+    {
+        let texels = (0..size * size).map(|i| Vec3::new(((i / size + 1) % 2) as f32)).collect();
+        return Texture::new(size, size, texels);
+    }
+
+    #[test]
+    fn test_nearest_sampling() {
+        let mut texture = texture();
+        texture.filter = Filter::Nearest;
+        assert_eq!(texture.sample(&Vec2::new_uv(0.9, 0.1)).r(), 1.0);
+        assert_eq!(texture.sample(&Vec2::new_uv(0.1, 0.9)).g(), 1.0);
+        // repeating wraps 1.1 around to the left column
+        assert_eq!(texture.sample(&Vec2::new_uv(1.1, 0.1)).r(), 0.0);
+        texture.wrap = Wrap::Clamp;
+        assert_eq!(texture.sample(&Vec2::new_uv(1.1, 0.1)).r(), 1.0);
+    }
+
+    #[test]
+    fn test_bilinear_sampling() {
+        let mut texture = texture();
+        let center = texture.sample(&Vec2::new_uv(0.5, 0.5));
+        assert_eq!((center.r(), center.g(), center.b()), (0.25, 0.25, 0.25));
+        let texel_center = texture.sample(&Vec2::new_uv(0.75, 0.25));
+        assert_eq!((texel_center.r(), texel_center.g()), (1.0, 0.0));
+        // halfway between the right column and the wrapped around left column
+        texture.wrap = Wrap::Repeat;
+        assert_eq!(texture.sample(&Vec2::new_uv(1.0, 0.25)).r(), 0.5);
+        texture.wrap = Wrap::Clamp;
+        assert_eq!(texture.sample(&Vec2::new_uv(1.0, 0.25)).r(), 1.0);
+    }
+
+    #[test]
+    fn test_mip_chain() {
+        let texture = stripes(4);
+        assert_eq!(texture.level_num(), 3);
+        assert_eq!(texture.levels[2].texels[0].r(), 0.5);
+        let odd = Texture::new(3, 1, vec![Vec3::new(0.0), Vec3::new(0.0), Vec3::new(0.9)]);
+        assert_eq!(odd.level_num(), 2);
+        assert!((odd.levels[1].texels[0].r() - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_trilinear_sampling_follows_the_footprint() {
+        let texture = stripes(8);
+        let uv = Vec2::new_uv(0.5, 0.5 / 8.0);
+        // one texel per pixel samples the full resolution level
+        let small = Vec2::new_uv(1.0 / 8.0, 0.0);
+        assert_eq!(texture.sample_grad(&uv, &small, &small.scalar_mul(0.0)).r(), 1.0);
+        // the whole texture in one pixel averages the stripes
+        let large = Vec2::new_uv(1.0, 0.0);
+        let large_v = Vec2::new_uv(0.0, 1.0);
+        assert!((texture.sample_grad(&uv, &large, &large_v).r() - 0.5).abs() < 1e-6);
+        // between two levels, the result is between them
+        let between = texture.sample_grad(&uv, &small.scalar_mul(1.5), &Vec2::new(0.0)).r();
+        assert!(between < 1.0 && between > 0.5);
+    }
+
+    #[test]
+    fn test_anisotropic_sampling_keeps_the_short_axis_sharp() {
+        let mut texture = stripes(8);
+        let uv = Vec2::new_uv(0.5, 0.5 / 8.0);
+        // the footprint covers the whole width but a single row
+        let duv_dx = Vec2::new_uv(1.0, 0.0);
+        let duv_dy = Vec2::new_uv(0.0, 1.0 / 8.0);
+        assert!((texture.sample_grad(&uv, &duv_dx, &duv_dy).r() - 0.5).abs() < 1e-6);
+        texture.max_anisotropy = 8;
+        assert!((texture.sample_grad(&uv, &duv_dx, &duv_dy).r() - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::buffer::DepthState;
+use crate::data::{Add, ScalarDiv, Vec3};
+use crate::shading::{Fragment, MAX_SAMPLES, Msaa, rasterize_triangle, sample_pattern, ScreenTriangle};
+
+/// Width and height of a tile in pixels, even so that 2x2 quads never straddle two tiles
+pub const TILE_SIZE: u32 = 32;
+
+/// A rectangle of pixels, upper bounds excluded
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile
+{
+    pub x_min: u32,
+    pub y_min: u32,
+    pub x_max: u32,
+    pub y_max: u32,
+}
+
+impl Tile
+{
+    #[inline]
+    pub fn width(&self) -> u32
+    {
+        self.x_max - self.x_min
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32
+    {
+        self.y_max - self.y_min
+    }
+
+    /// Index of a pixel of the frame in the row-major buffers of the tile
+    #[inline]
+    pub fn local_index(&self, x: u32, y: u32) -> usize
+    {
+        ((y - self.y_min) * self.width() + (x - self.x_min)) as usize
+    }
+
+    /// Frame coordinates of every pixel, in the order of `local_index`
+    pub fn pixels(&self) -> impl Iterator<Item=(u32, u32)> + '_
+    {
+        (self.y_min..self.y_max).flat_map(move |y| (self.x_min..self.x_max).map(move |x| (x, y)))
+    }
+}
+
+/// Number of tile columns and rows covering a frame
+#[inline]
+pub fn tile_grid(width: u32, height: u32) -> (u32, u32)
+{
+    ((width + TILE_SIZE - 1) / TILE_SIZE, (height + TILE_SIZE - 1) / TILE_SIZE)
+}
+
+/// Splits a frame into tiles, row by row from the bottom, tiles on the top and right edges may be smaller
+pub fn split_frame(width: u32, height: u32) -> Vec<Tile>
+{
+    let (columns, rows) = tile_grid(width, height);
+    let mut tiles = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows
+    {
+        for column in 0..columns
+        {
+            tiles.push(Tile {
+                x_min: column * TILE_SIZE,
+                y_min: row * TILE_SIZE,
+                x_max: u32::min((column + 1) * TILE_SIZE, width),
+                y_max: u32::min((row + 1) * TILE_SIZE, height),
+            });
+        }
+    }
+    return tiles;
+}
+
+/// Lists, for each tile of `split_frame`, the triangles whose bounding box overlaps it
+///
+/// Indices keep the order of `triangles`, so tiles resolve depth ties the same way.
+pub fn bin(triangles: &Vec<ScreenTriangle>, width: u32, height: u32) -> Vec<Vec<usize>>
+{
+    let (columns, rows) = tile_grid(width, height);
+    let mut bins = vec![Vec::new(); (columns * rows) as usize];
+    for (idx, triangle) in triangles.iter().enumerate()
+    {
+        if triangle.x_min >= triangle.x_max || triangle.y_min >= triangle.y_max
+        {
+            continue;
+        }
+        for row in (triangle.y_min / TILE_SIZE)..=((triangle.y_max - 1) / TILE_SIZE)
+        {
+            for column in (triangle.x_min / TILE_SIZE)..=((triangle.x_max - 1) / TILE_SIZE)
+            {
+                bins[(row * columns + column) as usize].push(idx);
+            }
+        }
+    }
+    return bins;
+}
+
+/// Width and height of the blocks of hierarchical Z, a divisor of `TILE_SIZE`
+pub const HIZ_BLOCK_SIZE: u32 = 8;
+
+/// Nearest and farthest depth stored in a block of pixels
+#[derive(Debug, Copy, Clone)]
+struct DepthBounds
+{
+    min: f32,
+    max: f32,
+}
+
+/// How much work the depth tests saved
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DepthStats
+{
+    /// covered samples that failed the early depth test, before attribute interpolation
+    pub early_z_rejected: usize,
+    /// samples of the bounding boxes of triangles within the blocks that hierarchical Z skipped, an
+    /// upper bound of the covered samples the early depth test would have rejected there
+    pub hiz_rejected: usize,
+}
+
+impl DepthStats
+{
+    pub fn merge(&self, other: &DepthStats) -> DepthStats
+    {
+        DepthStats {
+            early_z_rejected: self.early_z_rejected + other.early_z_rejected,
+            hiz_rejected: self.hiz_rejected + other.hiz_rejected,
+        }
+    }
+}
+
+/// Depth of every sample of one tile and the triangles covering them
+pub struct TileBuffer
+{
+    pub tile: Tile,
+    /// samples per pixel
+    pub samples: usize,
+    /// depth of every sample, the samples of a pixel next to each other in the order of `local_index`
+    pub depth: Vec<f32>,
+    /// for every sample, the index of the triangle that passed the depth test there last
+    pub coverage: Vec<Option<u32>>,
+    pub stats: DepthStats,
+}
+
+impl TileBuffer
+{
+    /// Rasterizes the binned triangles into the tile with the samples of `msaa`, keeping the last
+    /// triangle of each sample that passed the depth test
+    ///
+    /// No attribute is interpolated here, so the memory of a tile does not grow with overdraw.
+    /// The tile is split into blocks whose depth bounds let a triangle skip a block where it cannot
+    /// pass the test, or skip the test in a block where it passes everywhere.
+    pub fn rasterize(tile: Tile, triangle_idxs: &Vec<usize>, triangles: &Vec<ScreenTriangle>, state: &DepthState,
+                     msaa: Msaa) -> Self
+    {
+        let pattern = sample_pattern(msaa);
+        let samples = pattern.len();
+        let sample_num = (tile.width() * tile.height()) as usize * samples;
+        let mut buffer = TileBuffer {
+            tile,
+            samples,
+            depth: vec![state.clear; sample_num],
+            coverage: vec![None; sample_num],
+            stats: DepthStats::default(),
+        };
+        let func = state.func;
+        let blocks = split_tile(&tile);
+        let mut blocks_bounds = vec![DepthBounds { min: state.clear, max: state.clear }; blocks.len()];
+        for idx in triangle_idxs.iter()
+        {
+            let triangle = &triangles[*idx];
+            let z_range = triangle.depth_range();
+            for (block, bounds) in blocks.iter().zip(blocks_bounds.iter_mut())
+            {
+                if triangle.x_max <= block.x_min || triangle.x_min >= block.x_max
+                    || triangle.y_max <= block.y_min || triangle.y_min >= block.y_max
+                {
+                    continue;
+                }
+                if !func.any_passes(z_range, (bounds.min, bounds.max))
+                {
+                    // estimated from the bounding box, a skipped block does no per-pixel work
+                    let width = u32::min(triangle.x_max, block.x_max) - u32::max(triangle.x_min, block.x_min);
+                    let height = u32::min(triangle.y_max, block.y_max) - u32::max(triangle.y_min, block.y_min);
+                    buffer.stats.hiz_rejected += (width * height) as usize * samples;
+                    continue;
+                }
+                let accept_all = func.all_pass(z_range, (bounds.min, bounds.max));
+                let depth = &mut buffer.depth;
+                let coverage = &mut buffer.coverage;
+                let stats = &mut buffer.stats;
+                let mut written = false;
+                rasterize_triangle(triangle, (block.x_min, block.x_max), (block.y_min, block.y_max), pattern, |x, y, mask, depths| {
+                    let i = tile.local_index(x, y) * samples;
+                    for (s, z) in depths.iter().enumerate()
+                    {
+                        if mask & (1 << s) == 0
+                        {
+                            continue;
+                        }
+                        if accept_all || func.passes(*z, depth[i + s])
+                        {
+                            if state.write
+                            {
+                                depth[i + s] = *z;
+                                written = true;
+                            }
+                            coverage[i + s] = Some(*idx as u32);
+                        } else {
+                            stats.early_z_rejected += 1;
+                        }
+                    }
+                });
+                if written
+                {
+                    *bounds = block.pixels().flat_map(|(x, y)| {
+                        let i = tile.local_index(x, y) * samples;
+                        depth[i..i + samples].iter()
+                    }).fold(DepthBounds { min: f32::MAX, max: f32::MIN }, |b, d| DepthBounds { min: b.min.min(*d), max: b.max.max(*d) });
+                }
+            }
+        }
+        return buffer;
+    }
+
+    /// Colors of the pixels in the order of `local_index`, `None` where no sample is covered
+    ///
+    /// The fragment of each triangle still covering a pixel is interpolated and shaded once, then
+    /// each pixel averages the colors of its samples, taking `background` for the uncovered ones.
+    pub fn resolve<S>(&self, triangles: &Vec<ScreenTriangle>, background: Vec3, shade: S) -> Vec<Option<Vec3>>
+        where S: Fn(usize, &Fragment) -> Vec3
+    {
+        let pixels = self.tile.pixels().zip(self.coverage.chunks(self.samples));
+        return pixels.map(|((x, y), pixel)| {
+            if pixel.iter().all(|c| c.is_none())
+            {
+                return None;
+            }
+            // a pixel has at most one triangle per sample
+            let mut shaded: [(u32, Vec3); MAX_SAMPLES] = [(0, background); MAX_SAMPLES];
+            let mut shaded_num = 0;
+            let mut sum = Vec3::new(0.0);
+            for c in pixel.iter()
+            {
+                let color = match c {
+                    None => background,
+                    Some(idx) => match shaded[..shaded_num].iter().find(|(shaded_idx, _)| shaded_idx == idx) {
+                        Some((_, color)) => *color,
+                        None => {
+                            let triangle = &triangles[*idx as usize];
+                            let color = shade(triangle.object_idx, &triangle.fragment(x, y));
+                            shaded[shaded_num] = (*idx, color);
+                            shaded_num += 1;
+                            color
+                        }
+                    }
+                };
+                sum.add_(&color);
+            }
+            return Some(sum.scalar_div(self.samples as f32));
+        }).collect();
+    }
+}
+
+/// Splits a tile into blocks of hierarchical Z, row by row from the bottom
+fn split_tile(tile: &Tile) -> Vec<Tile>
+{
+    let mut blocks = Vec::new();
+    for y_min in (tile.y_min..tile.y_max).step_by(HIZ_BLOCK_SIZE as usize)
+    {
+        for x_min in (tile.x_min..tile.x_max).step_by(HIZ_BLOCK_SIZE as usize)
+        {
+            blocks.push(Tile {
+                x_min,
+                y_min,
+                x_max: u32::min(x_min + HIZ_BLOCK_SIZE, tile.x_max),
+                y_max: u32::min(y_min + HIZ_BLOCK_SIZE, tile.y_max),
+            });
+        }
+    }
+    return blocks;
+}
+
+#[cfg(test)]
+mod test {
+    use rayon::prelude::*;
+
+    use crate::clipping::DepthRange;
+    use crate::data::Vec4;
+    use crate::shading::{rasterization, setup_triangles, Triangle, Vertex};
+    use crate::transformations::perspective;
+
+    use super::*;
+
+    fn vertices() -> Vec<Vertex>
+    {
+        // two overlapping triangles at different depths and one covering the middle of the frame
+        let xyz = [
+            (-2.0, -2.0, -3.0), (2.0, -1.0, -3.0), (0.0, 2.0, -3.0),
+            (-1.0, 1.5, -2.0), (-1.5, -1.0, -4.0), (2.0, 1.0, -2.5),
+            (-0.3, -0.3, -1.0), (0.3, -0.3, -1.0), (0.0, 0.3, -1.0),
+        ];
+        xyz.iter().enumerate().map(|(idx, (x, y, z))| Vertex { position: Vec4::new_xyzw(*x, *y, *z, 1.0), idx }).collect()
+    }
+
+    #[test]
+    fn test_split_frame() {
+        let tiles = split_frame(70, 40);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], Tile { x_min: 64, y_min: 0, x_max: 70, y_max: 32 });
+        assert_eq!(tiles[5].height(), 8);
+        let pixels: usize = tiles.iter().map(|t| t.pixels().count()).sum();
+        assert_eq!(pixels, 70 * 40);
+    }
+
+    #[test]
+    fn test_tiles_match_a_single_depth_buffer() {
+        let (width, height) = (70, 50);
+        let vertices = vertices();
+        let triangles: Vec<Triangle> = (0..3).map(|i| Triangle::new(&vertices[3 * i], &vertices[3 * i + 1], &vertices[3 * i + 2])).collect();
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.4, 0.1, 10.0);
+
+        // reference: every fragment through one depth buffer
+        let state = DepthState::default();
+        let mut expected = vec![state.clear; (width * height) as usize];
+        for f in rasterization(&triangles, &perspective_mat, width, height).iter()
+        {
+            let i = (f.y * width + f.x) as usize;
+            expected[i] = f32::min(expected[i], f.z);
+        }
+
+        let screen_triangles = setup_triangles(&triangles, &perspective_mat, DepthRange::MinusOneToOne, width, height, 0);
+        let bins = bin(&screen_triangles, width, height);
+        let buffers: Vec<TileBuffer> = split_frame(width, height).into_par_iter().zip(bins.par_iter())
+            .map(|(tile, triangle_idxs)| TileBuffer::rasterize(tile, triangle_idxs, &screen_triangles, &state, Msaa::X1))
+            .collect();
+        let mut covered = 0;
+        for buffer in buffers.iter()
+        {
+            for (x, y) in buffer.tile.pixels()
+            {
+                let i = buffer.tile.local_index(x, y);
+                assert_eq!(buffer.depth[i], expected[(y * width + x) as usize]);
+                if let Some(idx) = buffer.coverage[i]
+                {
+                    let f = screen_triangles[idx as usize].fragment(x, y);
+                    assert_eq!((f.x, f.y, f.z), (x, y, buffer.depth[i]));
+                    covered += 1;
+                }
+            }
+        }
+        assert_eq!(covered, expected.iter().filter(|d| **d < state.clear).count());
+        assert!(covered > 0);
+    }
+
+    #[test]
+    fn test_hierarchical_z_skips_hidden_triangles() {
+        let (width, height) = (32, 32);
+        // a wall right in front of the camera, then a triangle behind it
+        let xyz = [
+            (-4.0, -2.0, -1.0), (4.0, -2.0, -1.0), (0.0, 6.0, -1.0),
+            (-1.0, -1.0, -3.0), (1.0, -1.0, -3.0), (0.0, 1.0, -3.0),
+        ];
+        let vertices: Vec<Vertex> = xyz.iter().enumerate()
+            .map(|(idx, (x, y, z))| Vertex { position: Vec4::new_xyzw(*x, *y, *z, 1.0), idx }).collect();
+        let triangles: Vec<Triangle> = (0..2).map(|i| Triangle::new(&vertices[3 * i], &vertices[3 * i + 1], &vertices[3 * i + 2])).collect();
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+        let screen_triangles = setup_triangles(&triangles, &perspective_mat, DepthRange::MinusOneToOne, width, height, 0);
+        let tile = split_frame(width, height)[0];
+        // clipping splits the wall into several triangles, which come first
+        let mut triangle_idxs: Vec<usize> = (0..screen_triangles.len()).collect();
+        let buffer = TileBuffer::rasterize(tile, &triangle_idxs, &screen_triangles, &DepthState::default(), Msaa::X1);
+        // the wall covers the whole tile, so the blocks under the hidden triangle are skipped
+        assert!(buffer.coverage.iter().all(|c| c.is_some()));
+        assert!(buffer.stats.hiz_rejected > 0);
+        assert_eq!(buffer.stats.early_z_rejected, 0);
+        // the estimate holds at least every sample the hidden triangle covers
+        let hidden = TileBuffer::rasterize(tile, &vec![screen_triangles.len() - 1], &screen_triangles,
+                                           &DepthState::default(), Msaa::X1);
+        assert!(buffer.stats.hiz_rejected >= hidden.coverage.iter().filter(|c| c.is_some()).count());
+        let wall_depth = screen_triangles[0].depth_range();
+        assert!(buffer.depth.iter().all(|d| *d >= wall_depth.0 && *d <= wall_depth.1));
+
+        // drawn the other way round, the hidden triangle passes the depth test first and the wall then
+        // passes everywhere over it, so neither test rejects anything
+        triangle_idxs.reverse();
+        let buffer = TileBuffer::rasterize(tile, &triangle_idxs, &screen_triangles, &DepthState::default(), Msaa::X1);
+        assert_eq!((buffer.stats.hiz_rejected, buffer.stats.early_z_rejected), (0, 0));
+        assert!(buffer.depth.iter().all(|d| *d >= wall_depth.0 && *d <= wall_depth.1));
+    }
+
+    #[test]
+    fn test_multisampled_edges_are_blended() {
+        let (width, height) = (32, 32);
+        // a rectangle whose right edge runs through the pixel centers of column 16
+        let e = 0.5 / 16.0;
+        let xyz = [(-2.0, -2.0, -1.0), (e, -2.0, -1.0), (e, 2.0, -1.0), (-2.0, 2.0, -1.0)];
+        let vertices: Vec<Vertex> = xyz.iter().enumerate()
+            .map(|(idx, (x, y, z))| Vertex { position: Vec4::new_xyzw(*x, *y, *z, 1.0), idx }).collect();
+        let triangles = vec![
+            Triangle::new(&vertices[0], &vertices[1], &vertices[2]),
+            Triangle::new(&vertices[0], &vertices[2], &vertices[3]),
+        ];
+        let perspective_mat = perspective(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+        let screen_triangles = setup_triangles(&triangles, &perspective_mat, DepthRange::MinusOneToOne, width, height, 0);
+        let tile = split_frame(width, height)[0];
+        let triangle_idxs: Vec<usize> = (0..screen_triangles.len()).collect();
+        let white = Vec3::new(1.0);
+        let black = Vec3::new(0.0);
+
+        let buffer = TileBuffer::rasterize(tile, &triangle_idxs, &screen_triangles, &DepthState::default(), Msaa::X1);
+        let colors = buffer.resolve(&screen_triangles, black, |_, _| white);
+        assert_eq!(colors[tile.local_index(15, 8)].unwrap().x(), 1.0);
+        assert!(colors[tile.local_index(16, 8)].is_none());
+
+        // two of the four samples of column 16 lie left of the edge
+        let buffer = TileBuffer::rasterize(tile, &triangle_idxs, &screen_triangles, &DepthState::default(), Msaa::X4);
+        let shaded = std::cell::Cell::new(0);
+        let colors = buffer.resolve(&screen_triangles, black, |_, _| {
+            shaded.set(shaded.get() + 1);
+            white
+        });
+        assert_eq!(colors[tile.local_index(15, 8)].unwrap().x(), 1.0);
+        assert_eq!(colors[tile.local_index(16, 8)].unwrap().x(), 0.5);
+        assert!(colors[tile.local_index(17, 8)].is_none());
+        assert_eq!(buffer.depth.len(), 4 * 32 * 32);
+        // fragments are shaded once per triangle in a pixel, not per sample
+        let covered_samples = buffer.coverage.iter().filter(|c| c.is_some()).count();
+        let fragments: usize = buffer.coverage.chunks(4).map(|pixel| {
+            let mut idxs: Vec<u32> = pixel.iter().flatten().cloned().collect();
+            idxs.sort();
+            idxs.dedup();
+            idxs.len()
+        }).sum();
+        assert_eq!(shaded.get(), fragments);
+        assert!(shaded.get() < covered_samples);
+    }
+}
@@ -106,6 +106,36 @@ pub fn perspective(fovy_rad: f32, aspect: f32, near: f32, far: f32) -> Mat4
     return mat;
 }
 
+/// `perspective` with the far plane at infinity, depth still goes from -1 at the near plane to 1
+pub fn perspective_infinite(fovy_rad: f32, aspect: f32, near: f32) -> Mat4
+{
+    let mut mat = perspective(fovy_rad, aspect, near, near * 2.0);
+    mat._set_entry(2, 2, -1.0);
+    mat._set_entry(2, 3, -2.0 * near);
+    return mat;
+}
+
+/// Reversed-Z projection, depth goes from 1 at the near plane to 0 at the far plane
+///
+/// Floats are densest around 0, so the precision lost to the perspective division far away is
+/// given back. Clip with `DepthRange::ZeroToOne` and keep the larger depth.
+pub fn perspective_reversed(fovy_rad: f32, aspect: f32, near: f32, far: f32) -> Mat4
+{
+    let mut mat = perspective(fovy_rad, aspect, near, far);
+    mat._set_entry(2, 2, near / (far - near));
+    mat._set_entry(2, 3, far * near / (far - near));
+    return mat;
+}
+
+/// `perspective_reversed` with the far plane at infinity, where depth reaches 0
+pub fn perspective_reversed_infinite(fovy_rad: f32, aspect: f32, near: f32) -> Mat4
+{
+    let mut mat = perspective(fovy_rad, aspect, near, near * 2.0);
+    mat._set_entry(2, 2, 0.0);
+    mat._set_entry(2, 3, near);
+    return mat;
+}
+
 #[cfg(test)]
 mod test {
     use crate::data::{MatVecDot, ScalarDiv};
@@ -230,4 +260,29 @@ mod test {
         let pp = proj_mat.mat_vec_dot(&p);
         println!("{:?}", pp);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_perspective_depth_ranges()
+    {
+        let ndc_z = |mat: &Mat4, z: f32| {
+            let p = mat.mat_vec_dot(&Vec4::new_xyzw(0.3, -0.2, z, 1.0));
+            return p.z() / p.w();
+        };
+        let (fovy, near, far) = (1.0, 0.5, 50.0);
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        let standard = perspective(fovy, 1.0, near, far);
+        assert!(close(ndc_z(&standard, -near), -1.0) && close(ndc_z(&standard, -far), 1.0));
+        let infinite = perspective_infinite(fovy, 1.0, near);
+        assert!(close(ndc_z(&infinite, -near), -1.0) && close(ndc_z(&infinite, -1e7), 1.0));
+        let reversed = perspective_reversed(fovy, 1.0, near, far);
+        assert!(close(ndc_z(&reversed, -near), 1.0) && close(ndc_z(&reversed, -far), 0.0));
+        let reversed_infinite = perspective_reversed_infinite(fovy, 1.0, near);
+        assert!(close(ndc_z(&reversed_infinite, -near), 1.0) && close(ndc_z(&reversed_infinite, -1e7), 0.0));
+        // x and y project the same whatever the depth mapping
+        let p = Vec4::new_xyzw(0.3, -0.2, -4.0, 1.0);
+        assert_eq!(standard.mat_vec_dot(&p).x(), reversed_infinite.mat_vec_dot(&p).x());
+        // and reversed-Z keeps distant depths apart where the standard mapping rounds them together
+        assert_eq!(ndc_z(&infinite, -4e4), ndc_z(&infinite, -4.001e4));
+        assert_ne!(ndc_z(&reversed_infinite, -4e4), ndc_z(&reversed_infinite, -4.001e4));
+    }
+}